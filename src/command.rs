@@ -1,4 +1,142 @@
+pub mod cat;
 pub mod cd;
+pub mod dirs;
 pub mod exit;
+pub mod grep;
+pub mod help;
 pub mod logo;
 pub mod history;
+pub mod printenv;
+pub mod set;
+pub mod setenv;
+pub mod sort;
+pub mod wc;
+
+pub struct Builtin {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+// PATH に同名のコマンドが無いときだけ使う組み込みコマンド
+// (オプションは一部しか無いので、普段はシステムのものを優先する)
+pub const PATH_FIRST: &[&str] = &["cat", "sort", "grep", "wc"];
+
+// 組み込みコマンドの一覧と使い方・説明 (補完候補とhelpに使う)
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "cd",
+        usage: "cd [-L|-P] [dir]",
+        description: "change the current directory",
+    },
+    Builtin {
+        name: "pushd",
+        usage: "pushd [dir | +N]",
+        description: "push a directory onto the directory stack",
+    },
+    Builtin {
+        name: "popd",
+        usage: "popd",
+        description: "pop a directory off the directory stack",
+    },
+    Builtin {
+        name: "dirs",
+        usage: "dirs [-v]",
+        description: "show the directory stack",
+    },
+    Builtin {
+        name: "printenv",
+        usage: "printenv [name]",
+        description: "print all or one environment variable",
+    },
+    Builtin {
+        name: "setenv",
+        usage: "setenv [name [value]]",
+        description: "set an environment variable",
+    },
+    Builtin {
+        name: "history",
+        usage: "history [--relative]",
+        description: "show the command history (also %fl)",
+    },
+    Builtin {
+        name: "%fl",
+        usage: "%fl [--relative]",
+        description: "show the command history",
+    },
+    Builtin {
+        name: "logo",
+        usage: "logo",
+        description: "show the rsh logo (also %logo)",
+    },
+    Builtin {
+        name: "%logo",
+        usage: "%logo",
+        description: "show the rsh logo",
+    },
+    Builtin {
+        name: "set",
+        usage: "set [-e|+e] [-x|+x]",
+        description: "set shell options (-e to stop on failure, -x to trace commands)",
+    },
+    Builtin {
+        name: "cat",
+        usage: "cat [-n] [file...]",
+        description: "concatenate files or standard input",
+    },
+    Builtin {
+        name: "sort",
+        usage: "sort [-r] [-n] [-u] [file...]",
+        description: "sort lines of files or standard input",
+    },
+    Builtin {
+        name: "grep",
+        usage: "grep [-i] [-v] [-n] pattern [file...]",
+        description: "print lines of files or standard input containing a pattern",
+    },
+    Builtin {
+        name: "wc",
+        usage: "wc [-l] [-w] [-c] [file...]",
+        description: "count lines, words and bytes of files or standard input",
+    },
+    Builtin {
+        name: "repeat",
+        usage: "repeat N command...",
+        description: "run a command N times",
+    },
+    Builtin {
+        name: "r",
+        usage: "r",
+        description: "run the previous command again",
+    },
+    Builtin {
+        name: "fc",
+        usage: "fc",
+        description: "edit the previous command in $EDITOR and run it",
+    },
+    Builtin {
+        name: "time",
+        usage: "time command...",
+        description: "run a command and report how long it took",
+    },
+    Builtin {
+        name: "builtin",
+        usage: "builtin name [arg...]",
+        description: "run a builtin command, never an external one",
+    },
+    Builtin {
+        name: "rehash",
+        usage: "rehash [-v]",
+        description: "rescan PATH (-v notes commands hidden by builtins)",
+    },
+    Builtin {
+        name: "help",
+        usage: "help [name]",
+        description: "list the builtin commands or show how to use one",
+    },
+    Builtin {
+        name: "exit",
+        usage: "exit [status]",
+        description: "exit the shell",
+    },
+];
@@ -0,0 +1,94 @@
+use crate::{RshError, Status};
+use std::fs;
+use std::io::{self, Read, Write};
+
+pub fn rsh_cat(out: &mut impl Write, args: &[String]) -> Result<Status, RshError> {
+    let number = args.iter().any(|arg| arg == "-n");
+    let mut files: Vec<&str> = args
+        .iter()
+        .filter(|arg| *arg != "-n")
+        .map(String::as_str)
+        .collect();
+    // ファイルが指定されていなければ標準入力から読む
+    if files.is_empty() {
+        files.push("-");
+    }
+
+    let mut errors = Vec::new();
+    let mut line_number = 0;
+
+    for file in files {
+        let data = if file == "-" {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data).map(|_| data)
+        } else {
+            fs::read(file)
+        };
+        // 読めなかったファイルがあっても残りのファイルは出力する
+        let data = match data {
+            Ok(data) => data,
+            Err(err) => {
+                errors.push(format!("cat: {}: {}", file, err));
+                continue;
+            }
+        };
+
+        if number {
+            for line in data.split_inclusive(|&b| b == b'\n') {
+                line_number += 1;
+                let _ = write!(out, "{:>6}\t", line_number);
+                let _ = out.write_all(line);
+            }
+        } else {
+            let _ = out.write_all(&data);
+        }
+    }
+    let _ = out.flush();
+
+    if errors.is_empty() {
+        Ok(Status::Success)
+    } else {
+        Err(RshError::new(&errors.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_temp(name: &str, data: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rsh-cat-{}-{}", name, std::process::id()));
+        fs::write(&path, data).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn concatenates_and_numbers_lines() {
+        let a = write_temp("a", "one\ntwo\n");
+        let b = write_temp("b", "three");
+        let mut out = Vec::new();
+        rsh_cat(&mut out, &[a.clone(), b.clone()]).unwrap();
+        assert_eq!(out, b"one\ntwo\nthree");
+
+        // 番号はファイルをまたいで続ける
+        let mut out = Vec::new();
+        rsh_cat(&mut out, &["-n".to_string(), a.clone(), b.clone()]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "     1\tone\n     2\ttwo\n     3\tthree"
+        );
+        fs::remove_file(a).unwrap();
+        fs::remove_file(b).unwrap();
+    }
+
+    #[test]
+    fn missing_file_still_prints_the_rest() {
+        let a = write_temp("c", "one\n");
+        let missing = format!("{}-missing", a);
+        let mut out = Vec::new();
+        let err = rsh_cat(&mut out, &[missing.clone(), a.clone()]).unwrap_err();
+        assert_eq!(out, b"one\n");
+        assert!(err.message.starts_with(&format!("cat: {}: ", missing)));
+        fs::remove_file(a).unwrap();
+    }
+}
@@ -1,15 +1,154 @@
 use crate::{RshError, Status};
+use nix::errno::Errno;
 use nix::unistd::*;
-use std::path::Path;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
-pub fn rsh_cd(dir: &str) -> Result<Status, RshError> {
+fn describe_error(err: nix::Error) -> String {
+    // よくある失敗は分かりやすい文言にする
+    match err {
+        nix::Error::Sys(Errno::ENOENT) => "no such file or directory".to_string(),
+        nix::Error::Sys(Errno::ENOTDIR) => "not a directory".to_string(),
+        nix::Error::Sys(Errno::EACCES) => "permission denied".to_string(),
+        nix::Error::Sys(Errno::ENAMETOOLONG) => "file name too long".to_string(),
+        nix::Error::Sys(Errno::ELOOP) => "too many levels of symbolic links".to_string(),
+        _ => err.to_string(),
+    }
+}
+
+fn logical_pwd() -> Option<PathBuf> {
+    // $PWD が今いるディレクトリを指していればシンボリックリンクを含んだままの論理パスを使う
+    let current = env::current_dir().ok()?;
+    match env::var("PWD") {
+        Ok(pwd)
+            if Path::new(&pwd).is_absolute()
+                && fs::canonicalize(&pwd).ok() == Some(current.clone()) =>
+        {
+            Some(PathBuf::from(pwd))
+        }
+        _ => Some(current),
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    // . と .. を文字列の上で解決する (シンボリックリンクは辿らない)
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+            _ => {}
+        }
+    }
+    normalized
+}
+
+// physical が true なら -P: シンボリックリンクを解決した実際のパスに移動する
+pub fn rsh_cd(dir: &str, physical: bool) -> Result<Status, RshError> {
     if !dir.is_empty() {
-        // TODO: エラーハンドリング
-        let r = chdir(Path::new(dir))
-            .map(|_| Status::Success)
-            .map_err(|err| RshError::new(&err.to_string()));
-        r
+        let old_pwd = logical_pwd();
+        let target = match (&old_pwd, physical) {
+            (Some(base), false) => normalize(&base.join(dir)),
+            _ => PathBuf::from(dir),
+        };
+        chdir(&target)
+            .map_err(|err| RshError::new(&format!("cd: {}: {}", dir, describe_error(err))))?;
+
+        // 移動前と移動後のディレクトリを記録
+        if let Some(old_pwd) = old_pwd {
+            env::set_var("OLDPWD", old_pwd);
+        }
+        if physical {
+            if let Ok(pwd) = env::current_dir() {
+                env::set_var("PWD", pwd);
+            }
+        } else {
+            env::set_var("PWD", target);
+        }
+        Ok(Status::Success)
     } else {
-        Err(RshError::new("rsh: expected arguments to cd\n"))
+        Err(RshError::new("cd: expected arguments"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_errors_are_described_plainly() {
+        assert_eq!(
+            describe_error(nix::Error::Sys(Errno::ENOENT)),
+            "no such file or directory"
+        );
+        assert_eq!(
+            describe_error(nix::Error::Sys(Errno::ENOTDIR)),
+            "not a directory"
+        );
+        assert_eq!(
+            describe_error(nix::Error::Sys(Errno::EACCES)),
+            "permission denied"
+        );
+    }
+
+    #[test]
+    fn cd_reports_missing_directory_and_file() {
+        let base = env::temp_dir().join(format!("rsh-cd-error-{}", std::process::id()));
+        fs::create_dir_all(&base).unwrap();
+        let file = base.join("file");
+        fs::write(&file, "").unwrap();
+
+        let missing = base.join("missing");
+        let missing = missing.to_str().unwrap();
+        assert_eq!(
+            rsh_cd(missing, false).unwrap_err().message,
+            format!("cd: {}: no such file or directory", missing)
+        );
+        let file = file.to_str().unwrap();
+        assert_eq!(
+            rsh_cd(file, true).unwrap_err().message,
+            format!("cd: {}: not a directory", file)
+        );
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn normalize_resolves_dots_textually() {
+        assert_eq!(normalize(Path::new("/a/b/../c/.")), PathBuf::from("/a/c"));
+        assert_eq!(normalize(Path::new("/..")), PathBuf::from("/"));
+        assert_eq!(normalize(Path::new("/a/../../b")), PathBuf::from("/b"));
+    }
+
+    #[test]
+    fn logical_and_physical_pwd_through_symlink() {
+        let _state = crate::save_process_state();
+        let base = fs::canonicalize(env::temp_dir())
+            .unwrap()
+            .join(format!("rsh-cd-link-{}", std::process::id()));
+        let real = base.join("real");
+        let link = base.join("link");
+        fs::create_dir_all(real.join("sub")).unwrap();
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        let pwd = || PathBuf::from(env::var("PWD").unwrap());
+
+        rsh_cd(link.join("sub").to_str().unwrap(), false).unwrap();
+        assert_eq!(pwd(), link.join("sub"));
+        assert_eq!(env::current_dir().unwrap(), real.join("sub"));
+        // .. はリンクを辿る前の論理パスの上で解決する
+        rsh_cd("..", false).unwrap();
+        assert_eq!(pwd(), link);
+        assert_eq!(
+            env::var("OLDPWD").unwrap(),
+            link.join("sub").to_str().unwrap()
+        );
+
+        // -P ではシンボリックリンクを解決したパスになる
+        rsh_cd(link.to_str().unwrap(), true).unwrap();
+        assert_eq!(pwd(), real);
+        fs::remove_dir_all(base).unwrap();
     }
 }
@@ -0,0 +1,202 @@
+use crate::{command::cd::rsh_cd, RshError, Status};
+use std::env;
+use std::io::Write;
+
+fn current_dir_string() -> Result<String, RshError> {
+    env::current_dir()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .map_err(|err| RshError::new(&err.to_string()))
+}
+
+fn abbreviate_home(dir: &str) -> String {
+    // ホームディレクトリ以下は ~ で表示する
+    match env::var("HOME") {
+        Ok(home) if !home.is_empty() && dir.starts_with(&home) => {
+            let rest = &dir[home.len()..];
+            if rest.is_empty() || rest.starts_with('/') {
+                format!("~{}", rest)
+            } else {
+                dir.to_string()
+            }
+        }
+        _ => dir.to_string(),
+    }
+}
+
+fn print_stack(out: &mut impl Write, stack: &[String], verbose: bool) -> Result<(), RshError> {
+    let mut entries = vec![abbreviate_home(&current_dir_string()?)];
+    entries.extend(stack.iter().map(|dir| abbreviate_home(dir)));
+    let result = if verbose {
+        // -v: 一行に一つずつ番号付きで表示
+        entries
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, dir)| writeln!(out, "{:2}  {}", i, dir))
+    } else {
+        writeln!(out, "{}", entries.join(" "))
+    };
+    result.map_err(|err| RshError::new(&err.to_string()))
+}
+
+// 番号で指定されたスタックの要素 (0 は現在のディレクトリ、-N は末尾から数える)
+pub fn directory_stack_entry(stack: &[String], index: &str) -> Result<String, RshError> {
+    let out_of_range = || RshError::new(&format!("{}: directory stack index out of range", index));
+    let digits = index.trim_start_matches(['+', '-']);
+    let n = digits.parse::<usize>().map_err(|_| out_of_range())?;
+    let n = if index.starts_with('-') {
+        stack.len().checked_sub(n).ok_or_else(out_of_range)?
+    } else {
+        n
+    };
+    match n {
+        0 => current_dir_string(),
+        n => stack.get(n - 1).cloned().ok_or_else(out_of_range),
+    }
+}
+
+// スタックの先頭は現在のディレクトリ、以降は新しく積んだ順
+pub fn rsh_dirs(
+    out: &mut impl Write,
+    stack: &[String],
+    args: &[String],
+) -> Result<Status, RshError> {
+    let mut verbose = false;
+    for arg in args {
+        match arg.as_str() {
+            "-v" => verbose = true,
+            _ => return Err(RshError::new(&format!("dirs: {}: invalid option", arg))),
+        }
+    }
+    print_stack(out, stack, verbose)?;
+    Ok(Status::Success)
+}
+
+pub fn rsh_pushd(
+    out: &mut impl Write,
+    stack: &mut Vec<String>,
+    dir: Option<&str>,
+) -> Result<Status, RshError> {
+    let old_dir = current_dir_string()?;
+    match dir {
+        // +N: N番目の要素が先頭に来るようにスタックを回す
+        Some(index) if index.len() > 1 && index.starts_with('+') => {
+            let mut entries = vec![old_dir];
+            entries.extend(stack.iter().cloned());
+            let n = index[1..]
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n < entries.len())
+                .ok_or_else(|| {
+                    RshError::new(&format!(
+                        "pushd: {}: directory stack index out of range",
+                        index
+                    ))
+                })?;
+            entries.rotate_left(n);
+            rsh_cd(&entries[0], false)?;
+            *stack = entries.split_off(1);
+        }
+        Some(dir) => {
+            rsh_cd(dir, false)?;
+            stack.insert(0, old_dir);
+        }
+        // 引数が無い場合は先頭の二つを入れ替える
+        None => {
+            let Some(top) = stack.first().cloned() else {
+                return Err(RshError::new("pushd: no other directory"));
+            };
+            rsh_cd(&top, false)?;
+            stack[0] = old_dir;
+        }
+    }
+    print_stack(out, stack, false)?;
+    Ok(Status::Success)
+}
+
+pub fn rsh_popd(out: &mut impl Write, stack: &mut Vec<String>) -> Result<Status, RshError> {
+    let Some(top) = stack.first().cloned() else {
+        return Err(RshError::new("popd: directory stack empty"));
+    };
+    rsh_cd(&top, false)?;
+    stack.remove(0);
+    print_stack(out, stack, false)?;
+    Ok(Status::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dirs(name: &str) -> (String, String) {
+        let base = fs::canonicalize(env::temp_dir()).unwrap().join(format!(
+            "rsh-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let (a, b) = (base.join("a"), base.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        let path = |dir: PathBuf| dir.to_string_lossy().to_string();
+        (path(a), path(b))
+    }
+
+    #[test]
+    fn stack_entry_by_index() {
+        let stack = vec!["/b".to_string(), "/a".to_string(), "/".to_string()];
+        assert_eq!(directory_stack_entry(&stack, "1").unwrap(), "/b");
+        assert_eq!(directory_stack_entry(&stack, "+3").unwrap(), "/");
+        assert_eq!(directory_stack_entry(&stack, "-1").unwrap(), "/a");
+        assert_eq!(
+            directory_stack_entry(&stack, "0").unwrap(),
+            current_dir_string().unwrap()
+        );
+    }
+
+    #[test]
+    fn stack_entry_out_of_range() {
+        let stack = vec!["/b".to_string()];
+        for index in ["2", "-2", "x"] {
+            let err = directory_stack_entry(&stack, index).unwrap_err();
+            assert_eq!(
+                err.message,
+                format!("{}: directory stack index out of range", index)
+            );
+        }
+    }
+
+    #[test]
+    fn pushd_popd_order_and_swap() {
+        let _state = crate::save_process_state();
+        env::remove_var("HOME");
+        let (a, b) = temp_dirs("pushd-test");
+        rsh_cd(&a, false).unwrap();
+        let mut stack = Vec::new();
+
+        let mut out = Vec::new();
+        rsh_pushd(&mut out, &mut stack, Some(&b)).unwrap();
+        assert_eq!(stack, [a.as_str()]);
+        assert_eq!(current_dir_string().unwrap(), b);
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{} {}\n", b, a));
+
+        // 引数が無ければ先頭の二つを入れ替える
+        let mut out = Vec::new();
+        rsh_pushd(&mut out, &mut stack, None).unwrap();
+        assert_eq!(stack, [b.as_str()]);
+        assert_eq!(current_dir_string().unwrap(), a);
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{} {}\n", a, b));
+
+        let mut out = Vec::new();
+        rsh_popd(&mut out, &mut stack).unwrap();
+        assert!(stack.is_empty());
+        assert_eq!(current_dir_string().unwrap(), b);
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{}\n", b));
+
+        let err = rsh_popd(&mut Vec::new(), &mut stack).unwrap_err();
+        assert_eq!(err.message, "popd: directory stack empty");
+        let err = rsh_pushd(&mut Vec::new(), &mut stack, None).unwrap_err();
+        assert_eq!(err.message, "pushd: no other directory");
+        fs::remove_dir_all(PathBuf::from(a).parent().unwrap()).unwrap();
+    }
+}
@@ -1,6 +1,42 @@
 use crate::{RshError, Status};
+use std::io::Write;
 
-pub fn rsh_exit() -> Result<Status, RshError> {
-    println!("Bye");
-    Ok(Status::Exit)
+pub fn rsh_exit(
+    out: &mut impl Write,
+    code: Option<&str>,
+    last_status: i32,
+) -> Result<Status, RshError> {
+    // 引数が無い場合は直前のコマンドの終了ステータスで終了する
+    let code = match code {
+        Some(code) => code
+            .parse::<i32>()
+            .map_err(|_| RshError::new(&format!("exit: {}: numeric argument required", code)))?,
+        None => last_status,
+    };
+    writeln!(out, "Bye").map_err(|err| RshError::new(&err.to_string()))?;
+    Ok(Status::Exit(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_from_argument_or_last_status() {
+        let mut out = Vec::new();
+        assert!(matches!(rsh_exit(&mut out, None, 3), Ok(Status::Exit(3))));
+        assert_eq!(out, b"Bye\n");
+        assert!(matches!(
+            rsh_exit(&mut Vec::new(), Some("42"), 3),
+            Ok(Status::Exit(42))
+        ));
+    }
+
+    #[test]
+    fn exit_rejects_non_numeric_argument() {
+        let mut out = Vec::new();
+        let err = rsh_exit(&mut out, Some("abc"), 0).unwrap_err();
+        assert_eq!(err.message, "exit: abc: numeric argument required");
+        assert!(out.is_empty());
+    }
 }
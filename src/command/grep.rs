@@ -0,0 +1,128 @@
+use crate::{RshError, Status};
+use std::fs;
+use std::io::{self, Read, Write};
+
+pub fn rsh_grep(out: &mut impl Write, args: &[String]) -> Result<Status, RshError> {
+    let mut ignore_case = false;
+    let mut invert = false;
+    let mut number = false;
+    let mut pattern = None;
+    let mut files = Vec::new();
+
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && pattern.is_none() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'i' => ignore_case = true,
+                        'v' => invert = true,
+                        'n' => number = true,
+                        _ => {
+                            return Err(RshError::new(&format!(
+                                "grep: invalid option -- '{}'",
+                                flag
+                            )))
+                        }
+                    }
+                }
+            }
+            _ if pattern.is_none() => pattern = Some(arg.as_str()),
+            _ => files.push(arg.as_str()),
+        }
+    }
+    let Some(pattern) = pattern else {
+        return Err(RshError::new(
+            "grep: usage: grep [-i] [-v] [-n] pattern [file...]",
+        ));
+    };
+    // 正規表現は使わず、部分文字列として一致するかどうかで判定する
+    let pattern = if ignore_case {
+        pattern.to_lowercase()
+    } else {
+        pattern.to_string()
+    };
+    // 複数のファイルを読むときは行の先頭にファイル名を付ける
+    let show_name = files.len() > 1;
+    // ファイルが指定されていなければ標準入力から読む
+    if files.is_empty() {
+        files.push("-");
+    }
+
+    let mut errors = Vec::new();
+
+    for file in files {
+        let data = if file == "-" {
+            let mut data = String::new();
+            io::stdin().read_to_string(&mut data).map(|_| data)
+        } else {
+            fs::read_to_string(file)
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(err) => {
+                errors.push(format!("grep: {}: {}", file, err));
+                continue;
+            }
+        };
+
+        for (i, line) in data.lines().enumerate() {
+            let matched = if ignore_case {
+                line.to_lowercase().contains(&pattern)
+            } else {
+                line.contains(&pattern)
+            };
+            if matched == invert {
+                continue;
+            }
+            if show_name {
+                let _ = write!(out, "{}:", file);
+            }
+            if number {
+                let _ = write!(out, "{}:", i + 1);
+            }
+            let _ = writeln!(out, "{}", line);
+        }
+    }
+    let _ = out.flush();
+
+    if errors.is_empty() {
+        Ok(Status::Success)
+    } else {
+        Err(RshError::new(&errors.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep(args: &[&str], data: &str) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "rsh-grep-test-{}-{}",
+            std::process::id(),
+            args.join("")
+        ));
+        fs::write(&path, data).unwrap();
+        let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        args.push(path.to_string_lossy().to_string());
+        let mut out = Vec::new();
+        rsh_grep(&mut out, &args).unwrap();
+        fs::remove_file(&path).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn match_invert_and_ignore_case() {
+        let data = "Foo\nbar\nfoo bar\n";
+        assert_eq!(grep(&["foo"], data), "foo bar\n");
+        assert_eq!(grep(&["-i", "foo"], data), "Foo\nfoo bar\n");
+        assert_eq!(grep(&["-v", "foo"], data), "Foo\nbar\n");
+        assert_eq!(grep(&["-n", "bar"], data), "2:bar\n3:foo bar\n");
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let err = rsh_grep(&mut Vec::new(), &["-r".to_string(), "x".to_string()]).unwrap_err();
+        assert_eq!(err.message, "grep: invalid option -- 'r'");
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{command::BUILTINS, RshError, Status};
+use std::io::Write;
+
+pub fn rsh_help(out: &mut impl Write, name: Option<&str>) -> Result<Status, RshError> {
+    // 名前が指定されればその組み込みコマンドの使い方を表示
+    if let Some(name) = name {
+        let builtin = BUILTINS
+            .iter()
+            .find(|builtin| builtin.name == name)
+            .ok_or_else(|| RshError::new(&format!("help: no help topic for '{}'", name)))?;
+        writeln!(out, "usage: {}", builtin.usage)
+            .and_then(|_| writeln!(out, "    {}", builtin.description))
+            .map_err(|err| RshError::new(&err.to_string()))?;
+        return Ok(Status::Success);
+    }
+
+    let width = BUILTINS
+        .iter()
+        .map(|builtin| builtin.name.len())
+        .max()
+        .unwrap_or(0);
+    for builtin in BUILTINS {
+        writeln!(
+            out,
+            "{:width$}  {}",
+            builtin.name,
+            builtin.description,
+            width = width
+        )
+        .map_err(|err| RshError::new(&err.to_string()))?;
+    }
+    Ok(Status::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_for_a_known_topic() {
+        let mut out = Vec::new();
+        rsh_help(&mut out, Some("exit")).unwrap();
+        let exit = BUILTINS
+            .iter()
+            .find(|builtin| builtin.name == "exit")
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("usage: {}\n    {}\n", exit.usage, exit.description)
+        );
+    }
+
+    #[test]
+    fn unknown_topic_is_an_error() {
+        let err = rsh_help(&mut Vec::new(), Some("nope")).unwrap_err();
+        assert_eq!(err.message, "help: no help topic for 'nope'");
+    }
+
+    #[test]
+    fn listing_has_one_line_per_builtin() {
+        let mut out = Vec::new();
+        rsh_help(&mut out, None).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap().lines().count(),
+            BUILTINS.len()
+        );
+    }
+}
@@ -1,9 +1,139 @@
 use crate::{log::log_maneger::History, RshError};
+use chrono::{Local, NaiveDateTime};
+use crossterm::{
+    cursor::MoveToColumn,
+    event::{read, Event, KeyCode, KeyEvent},
+    execute,
+    style::Print,
+    terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType},
+};
+use std::io::{self, IsTerminal, Write};
 
+// 履歴に書き込む時刻の形式
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
-pub fn rsh_history(database: Vec<History>) -> Result<(), RshError> {
-    for (_, history) in database.iter().enumerate() {
-        println!("{} {}", history.get_time(), history.get_command());
+pub fn rsh_history(
+    out: &mut impl Write,
+    database: Vec<History>,
+    option: Option<&str>,
+) -> Result<(), RshError> {
+    let relative = match option {
+        None => false,
+        Some("--relative") => true,
+        Some(option) => {
+            return Err(RshError::new(&format!(
+                "history: {}: invalid option",
+                option
+            )))
+        }
+    };
+
+    let now = Local::now().naive_local();
+    // 番号の桁数に合わせて右寄せする
+    let width = database.len().to_string().len();
+    let lines: Vec<String> = database
+        .iter()
+        .enumerate()
+        .map(|(i, history)| {
+            let time = match NaiveDateTime::parse_from_str(history.get_time(), TIME_FORMAT) {
+                Ok(then) if relative => format_relative_time(now, then),
+                // 読めない時刻はそのまま表示する
+                _ => history.get_time().to_string(),
+            };
+            // !n で呼び出せるよう1始まりの番号を付ける
+            format!(
+                "{:>width$}  {} {}",
+                i + 1,
+                time,
+                history.get_command(),
+                width = width
+            )
+        })
+        .collect();
+
+    print_paged(out, &lines, page_height())
+}
+
+fn page_height() -> usize {
+    // 端末に出力していない場合 (パイプなど) はページに分けない
+    match terminal::size() {
+        Ok((_, height)) if height > 1 && io::stdout().is_terminal() => usize::from(height) - 1,
+        _ => usize::MAX,
+    }
+}
+
+fn print_paged(out: &mut impl Write, lines: &[String], height: usize) -> Result<(), RshError> {
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 && i % height == 0 && !wait_next_page()? {
+            break;
+        }
+        writeln!(out, "{}", line).map_err(|err| RshError::new(&err.to_string()))?;
     }
     Ok(())
 }
+
+fn format_relative_time(now: NaiveDateTime, then: NaiveDateTime) -> String {
+    let seconds = (now - then).num_seconds().max(0);
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn wait_next_page() -> Result<bool, RshError> {
+    // 次のページに進むならtrue、q か Esc で中断するならfalse
+    let mut stdout = io::stdout();
+    execute!(stdout, Print("-- More -- (q to quit)"))
+        .map_err(|err| RshError::new(&err.to_string()))?;
+    enable_raw_mode().map_err(|err| RshError::new(&err.to_string()))?;
+    let next = loop {
+        if let Ok(Event::Key(KeyEvent { code, .. })) = read() {
+            break !matches!(code, KeyCode::Char('q') | KeyCode::Esc);
+        }
+    };
+    disable_raw_mode().map_err(|err| RshError::new(&err.to_string()))?;
+    execute!(stdout, MoveToColumn(0), Clear(ClearType::CurrentLine))
+        .map_err(|err| RshError::new(&err.to_string()))?;
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_time_units() {
+        let now = NaiveDateTime::parse_from_str("2024-01-02 00:00:00", TIME_FORMAT).unwrap();
+        let ago = |seconds| now - chrono::Duration::seconds(seconds);
+        assert_eq!(format_relative_time(now, ago(5)), "5s ago");
+        assert_eq!(format_relative_time(now, ago(120)), "2m ago");
+        assert_eq!(format_relative_time(now, ago(7200)), "2h ago");
+        assert_eq!(format_relative_time(now, ago(86400)), "1d ago");
+        // 未来の時刻は0秒前として扱う
+        assert_eq!(format_relative_time(now, ago(-10)), "0s ago");
+    }
+
+    #[test]
+    fn listing_has_right_aligned_numbers() {
+        let database: Vec<History> = (1..=10)
+            .map(|i| History::new(format!("cmd{}", i), "2024-01-02 00:00:00".to_string()))
+            .collect();
+        let mut out = Vec::new();
+        rsh_history(&mut out, database, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], " 1  2024-01-02 00:00:00 cmd1");
+        assert_eq!(lines[9], "10  2024-01-02 00:00:00 cmd10");
+    }
+
+    #[test]
+    fn unpaged_output_prints_every_line() {
+        let lines: Vec<String> = (0..500).map(|i| i.to_string()).collect();
+        let mut out = Vec::new();
+        print_paged(&mut out, &lines, usize::MAX).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), lines.join("\n") + "\n");
+    }
+}
@@ -1,32 +1,56 @@
 use crate::{RshError, Status};
+use std::io::{self, Write};
 
-pub fn rsh_logo() -> Result<Status, RshError> {
-    println!("                                    ...................");
-    println!("                             ..,77!                     _7!.");
-    println!(" `       `  `  `         .,7!.!       ...1.   `              .7&,   `                   `    `  `");
-    println!(
+pub fn rsh_logo(out: &mut impl Write) -> Result<Status, RshError> {
+    write_logo(out).map_err(|err| RshError::new(&err.to_string()))?;
+    Ok(Status::Success)
+}
+
+fn write_logo(out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "                                    ..................."
+    )?;
+    writeln!(
+        out,
+        "                             ..,77!                     _7!."
+    )?;
+    writeln!(out, " `       `  `  `         .,7!.!       ...1.   `              .7&,   `                   `    `  `")?;
+    writeln!(
+        out,
         "      `           `  .(7`    \\     ` .\\   ,,   `     `  `      ( .7..     `     `  `"
-    );
-    println!("  `       `  `    .?!       ,   `    J  .: ,,    `        `     )    7i,`    `       `  `  `   `");
-    println!("    `  `        ,^     `    ]        %, .; .,,     `        `   (       ?1,      `               `");
-    println!(" `         `  .^    `      .(  `  ` .:t .: \\ (  `    `  `        [        `?(       `   `   `");
-    println!("      `  `  ./     `    `  ,        , -, `.`  t            `     j   `       ?,   `        `  `");
-    println!("  `        .^   `     `    ,        ,   .J.   ,.  `   `      `   ,     `       4,     `         `");
-    println!(
+    )?;
+    writeln!(out, "  `       `  `    .?!       ,   `    J  .: ,,    `        `     )    7i,`    `       `  `  `   `")?;
+    writeln!(out, "    `  `        ,^     `    ]        %, .; .,,     `        `   (       ?1,      `               `")?;
+    writeln!(out, " `         `  .^    `      .(  `  ` .:t .: \\ (  `    `  `        [        `?(       `   `   `")?;
+    writeln!(out, "      `  `  ./     `    `  ,        , -, `.`  t            `     j   `       ?,   `        `  `")?;
+    writeln!(out, "  `        .^   `     `    ,        ,   .J.   ,.  `   `      `   ,     `       4,     `         `")?;
+    writeln!(
+        out,
         "    `         .   `        ,  ` `   ,.7!] r.<?~[        `        ,        `     .i      `"
-    );
-    println!(" `    `  `  t 1    `   `   ,      ` ,   ?7    `]   `      `   `  ,`  `            4.      `  `");
-    println!("            (  5.   `    ` ,        ,. ] . 1   ]     `     `     ,     `   `       (.  `       `");
-    println!("   `   `    ,   ?,          ) `  `   %`] ]  3  \\  `    `         ,       `   `  `   1.     `     `");
-    println!(" `       `   l    5,  `     1        ,.t t  ,./          `   `  `J `  `            .=    `    `");
-    println!(
+    )?;
+    writeln!(out, " `    `  `  t 1    `   `   ,      ` ,   ?7    `]   `      `   `  ,`  `            4.      `  `")?;
+    writeln!(out, "            (  5.   `    ` ,        ,. ] . 1   ]     `     `     ,     `   `       (.  `       `")?;
+    writeln!(out, "   `   `    ,   ?,          ) `  `   %`] ]  3  \\  `    `         ,       `   `  `   1.     `     `")?;
+    writeln!(out, " `       `   l    5,  `     1        ,.t t  ,./          `   `  `J `  `            .=    `    `")?;
+    writeln!(
+        out,
         "    `          i.   ?i,  `  ,,        t  (   (  `   `           .\\        `   ` ..Z."
-    );
-    println!("      `   `     ,i     .7(,  1  `  `  ,+ . .?         `   `   ` ,    `       .JV");
-    println!("                   7+.     ?i,                                 .^      `..JV7^");
-    println!("   `                  7(,     ?=..                           :w^  ` ..wV7");
-    println!("     `   `   `  `        7..      _71....   `      `  .........(?7&?!                         `  `");
-    println!(" `        `       `         ?7(,           _????!!``       ...?7!               `  `   `  `");
-    println!("    `  `      `    `             ?7<<... ..       ....(?=`             `  `  `               `");
-    Ok(Status::Success)
+    )?;
+    writeln!(
+        out,
+        "      `   `     ,i     .7(,  1  `  `  ,+ . .?         `   `   ` ,    `       .JV"
+    )?;
+    writeln!(
+        out,
+        "                   7+.     ?i,                                 .^      `..JV7^"
+    )?;
+    writeln!(
+        out,
+        "   `                  7(,     ?=..                           :w^  ` ..wV7"
+    )?;
+    writeln!(out, "     `   `   `  `        7..      _71....   `      `  .........(?7&?!                         `  `")?;
+    writeln!(out, " `        `       `         ?7(,           _????!!``       ...?7!               `  `   `  `")?;
+    writeln!(out, "    `  `      `    `             ?7<<... ..       ....(?=`             `  `  `               `")?;
+    Ok(())
 }
@@ -0,0 +1,49 @@
+use crate::{RshError, Status};
+use std::env;
+use std::io::Write;
+
+pub fn rsh_printenv(out: &mut impl Write, name: Option<&str>) -> Result<Status, RshError> {
+    match name {
+        Some(name) => match env::var(name) {
+            Ok(value) => {
+                writeln!(out, "{}", value).map_err(|err| RshError::new(&err.to_string()))?;
+                Ok(Status::Success)
+            }
+            Err(_) => Err(RshError::new(&format!("printenv: {}: not set", name))),
+        },
+        None => {
+            for (key, value) in env::vars() {
+                writeln!(out, "{}={}", key, value)
+                    .map_err(|err| RshError::new(&err.to_string()))?;
+            }
+            Ok(Status::Success)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_one_variable_or_all() {
+        env::set_var("RSH_PRINTENV_T", "a b");
+        let mut out = Vec::new();
+        rsh_printenv(&mut out, Some("RSH_PRINTENV_T")).unwrap();
+        assert_eq!(out, b"a b\n");
+
+        let mut out = Vec::new();
+        rsh_printenv(&mut out, None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().any(|line| line == "RSH_PRINTENV_T=a b"));
+    }
+
+    #[test]
+    fn unset_variable_is_an_error() {
+        env::remove_var("RSH_PRINTENV_UNSET_T");
+        let mut out = Vec::new();
+        let err = rsh_printenv(&mut out, Some("RSH_PRINTENV_UNSET_T")).unwrap_err();
+        assert_eq!(err.message, "printenv: RSH_PRINTENV_UNSET_T: not set");
+        assert!(out.is_empty());
+    }
+}
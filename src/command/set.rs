@@ -0,0 +1,74 @@
+use crate::{RshError, Status};
+use std::io::Write;
+
+// set で切り替えるシェルのオプション
+#[derive(Default)]
+pub struct ShellOptions {
+    // -e: コマンドが失敗したら残りを実行せずに終了する
+    pub errexit: bool,
+    // -x: 実行前にコマンドを表示する
+    pub xtrace: bool,
+}
+
+pub fn rsh_set(
+    out: &mut impl Write,
+    args: &[String],
+    options: &mut ShellOptions,
+) -> Result<Status, RshError> {
+    // 引数が無い場合は現在の設定を表示
+    if args.is_empty() {
+        let state = |enabled: bool| if enabled { "on" } else { "off" };
+        writeln!(out, "errexit\t{}", state(options.errexit))
+            .and_then(|_| writeln!(out, "xtrace\t{}", state(options.xtrace)))
+            .map_err(|err| RshError::new(&err.to_string()))?;
+        return Ok(Status::Success);
+    }
+
+    for arg in args {
+        let (enable, flags) = if let Some(flags) = arg.strip_prefix('-') {
+            (true, flags)
+        } else if let Some(flags) = arg.strip_prefix('+') {
+            (false, flags)
+        } else {
+            return Err(RshError::new(&format!("set: {}: invalid argument", arg)));
+        };
+        for flag in flags.chars() {
+            match flag {
+                'e' => options.errexit = enable,
+                'x' => options.xtrace = enable,
+                _ => return Err(RshError::new(&format!("set: -{}: invalid option", flag))),
+            }
+        }
+    }
+    Ok(Status::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_and_prints_xtrace() {
+        let mut options = ShellOptions::default();
+        rsh_set(&mut Vec::new(), &["-x".to_string()], &mut options).unwrap();
+        assert!(options.xtrace);
+
+        let mut out = Vec::new();
+        rsh_set(&mut out, &[], &mut options).unwrap();
+        assert_eq!(out, b"errexit\toff\nxtrace\ton\n");
+
+        rsh_set(&mut Vec::new(), &["+x".to_string()], &mut options).unwrap();
+        assert!(!options.xtrace);
+        assert!(rsh_set(&mut Vec::new(), &["-q".to_string()], &mut options).is_err());
+    }
+
+    #[test]
+    fn toggles_errexit() {
+        let mut options = ShellOptions::default();
+        rsh_set(&mut Vec::new(), &["-ex".to_string()], &mut options).unwrap();
+        assert!(options.errexit && options.xtrace);
+
+        rsh_set(&mut Vec::new(), &["+e".to_string()], &mut options).unwrap();
+        assert!(!options.errexit && options.xtrace);
+    }
+}
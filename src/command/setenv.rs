@@ -0,0 +1,24 @@
+use crate::{RshError, Status};
+use std::env;
+use std::io::Write;
+
+pub fn rsh_setenv(out: &mut impl Write, args: &[String]) -> Result<Status, RshError> {
+    if let Some(name) = args.first() {
+        if name.is_empty() || name.contains('=') || name.contains('\0') {
+            return Err(RshError::new(&format!("setenv: {}: invalid name", name)));
+        }
+    }
+    match args {
+        // 引数が無い場合は一覧を表示
+        [] => crate::command::printenv::rsh_printenv(out, None),
+        [name] => {
+            env::set_var(name, "");
+            Ok(Status::Success)
+        }
+        [name, value] => {
+            env::set_var(name, value);
+            Ok(Status::Success)
+        }
+        _ => Err(RshError::new("setenv: too many arguments")),
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{RshError, Status};
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, Read, Write};
+
+fn numeric_key(line: &str) -> f64 {
+    // 先頭の数値部分で比較する (数値でない行は0として扱う)
+    let line = line.trim_start();
+    let end = line
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && c == '-')))
+        .map(|(i, _)| i)
+        .unwrap_or(line.len());
+    line[..end].parse().unwrap_or(0.0)
+}
+
+// sort の -r, -n, -u
+#[derive(Default)]
+struct SortOptions {
+    reverse: bool,
+    numeric: bool,
+    unique: bool,
+}
+
+fn sort_lines<'a>(lines: &[&'a str], options: &SortOptions) -> Vec<&'a str> {
+    let compare = |a: &&str, b: &&str| -> Ordering {
+        if options.numeric {
+            numeric_key(a)
+                .partial_cmp(&numeric_key(b))
+                .unwrap_or(Ordering::Equal)
+        } else {
+            a.cmp(b)
+        }
+    };
+
+    let mut lines = lines.to_vec();
+    lines.sort_by(|a, b| compare(a, b).then_with(|| a.cmp(b)));
+    if options.unique {
+        lines.dedup_by(|a, b| compare(&&**a, &&**b) == Ordering::Equal);
+    }
+    if options.reverse {
+        lines.reverse();
+    }
+    lines
+}
+
+pub fn rsh_sort(out: &mut impl Write, args: &[String]) -> Result<Status, RshError> {
+    let mut options = SortOptions::default();
+    let mut files = Vec::new();
+
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'r' => options.reverse = true,
+                        'n' => options.numeric = true,
+                        'u' => options.unique = true,
+                        _ => {
+                            return Err(RshError::new(&format!(
+                                "sort: invalid option -- '{}'",
+                                flag
+                            )))
+                        }
+                    }
+                }
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    // ファイルが指定されていなければ標準入力から読む
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin()
+            .read_to_string(&mut input)
+            .map_err(|err| RshError::new(&format!("sort: {}", err)))?;
+    }
+    for file in files {
+        let data = fs::read_to_string(file)
+            .map_err(|err| RshError::new(&format!("sort: {}: {}", file, err)))?;
+        input.push_str(&data);
+        if !input.ends_with('\n') {
+            input.push('\n');
+        }
+    }
+
+    let lines: Vec<&str> = input.lines().collect();
+    for line in sort_lines(&lines, &options) {
+        writeln!(out, "{}", line).map_err(|err| RshError::new(&err.to_string()))?;
+    }
+    Ok(Status::Success)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINES: [&str; 6] = ["b", "10", "a", "9", "b", "09"];
+
+    #[test]
+    fn lexical_and_reverse() {
+        let options = SortOptions::default();
+        assert_eq!(
+            sort_lines(&LINES, &options),
+            ["09", "10", "9", "a", "b", "b"]
+        );
+        let options = SortOptions {
+            reverse: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            sort_lines(&LINES, &options),
+            ["b", "b", "a", "9", "10", "09"]
+        );
+    }
+
+    #[test]
+    fn numeric_and_unique() {
+        // 数値でない行は0として先頭に来る
+        let options = SortOptions {
+            numeric: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            sort_lines(&LINES, &options),
+            ["a", "b", "b", "09", "9", "10"]
+        );
+        let options = SortOptions {
+            unique: true,
+            ..Default::default()
+        };
+        assert_eq!(sort_lines(&LINES, &options), ["09", "10", "9", "a", "b"]);
+        // -nu では数値として等しい行を一つにまとめる
+        let options = SortOptions {
+            numeric: true,
+            unique: true,
+            ..Default::default()
+        };
+        assert_eq!(sort_lines(&LINES, &options), ["a", "09", "10"]);
+    }
+}
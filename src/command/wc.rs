@@ -0,0 +1,118 @@
+use crate::{RshError, Status};
+use std::fs;
+use std::io::{self, Read, Write};
+
+#[derive(Default)]
+struct Counts {
+    lines: usize,
+    words: usize,
+    bytes: usize,
+}
+
+fn count(data: &[u8]) -> Counts {
+    Counts {
+        lines: data.iter().filter(|&&b| b == b'\n').count(),
+        words: data
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .count(),
+        bytes: data.len(),
+    }
+}
+
+pub fn rsh_wc(out: &mut impl Write, args: &[String]) -> Result<Status, RshError> {
+    let mut lines = false;
+    let mut words = false;
+    let mut bytes = false;
+    let mut files = Vec::new();
+
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for flag in flags.chars() {
+                    match flag {
+                        'l' => lines = true,
+                        'w' => words = true,
+                        'c' => bytes = true,
+                        _ => {
+                            return Err(RshError::new(&format!("wc: invalid option -- '{}'", flag)))
+                        }
+                    }
+                }
+            }
+            _ => files.push(arg.as_str()),
+        }
+    }
+    // 何も指定されなければ行数・単語数・バイト数をすべて出す
+    if !(lines || words || bytes) {
+        (lines, words, bytes) = (true, true, true);
+    }
+    let show_total = files.len() > 1;
+    // ファイルが指定されていなければ標準入力から読む
+    if files.is_empty() {
+        files.push("-");
+    }
+
+    let mut errors = Vec::new();
+    let mut total = Counts::default();
+    let mut print = |counts: &Counts, name: &str| {
+        let mut columns = Vec::new();
+        if lines {
+            columns.push(format!("{:>7}", counts.lines));
+        }
+        if words {
+            columns.push(format!("{:>7}", counts.words));
+        }
+        if bytes {
+            columns.push(format!("{:>7}", counts.bytes));
+        }
+        if name != "-" {
+            columns.push(name.to_string());
+        }
+        let _ = writeln!(out, "{}", columns.join(" "));
+    };
+
+    for file in files {
+        let data = if file == "-" {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data).map(|_| data)
+        } else {
+            fs::read(file)
+        };
+        // 読めなかったファイルがあっても残りのファイルは数える
+        let data = match data {
+            Ok(data) => data,
+            Err(err) => {
+                errors.push(format!("wc: {}: {}", file, err));
+                continue;
+            }
+        };
+        let counts = count(&data);
+        total.lines += counts.lines;
+        total.words += counts.words;
+        total.bytes += counts.bytes;
+        print(&counts, file);
+    }
+    if show_total {
+        print(&total, "total");
+    }
+
+    if errors.is_empty() {
+        Ok(Status::Success)
+    } else {
+        Err(RshError::new(&errors.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_lines_words_bytes() {
+        let counts = count(b"one two\n  three\n\nfour");
+        assert_eq!((counts.lines, counts.words, counts.bytes), (3, 4, 21));
+        let counts = count(b"");
+        assert_eq!((counts.lines, counts.words, counts.bytes), (0, 0, 0));
+    }
+}
@@ -14,5 +14,5 @@ impl RshError {
 #[derive(Debug)]
 pub enum Status {
     Success,
-    Exit,
+    Exit(i32),
 }
@@ -22,10 +22,32 @@ impl History {
     }
 }
 
+// 複数行のコマンドも一行に収まるように \ と改行をエスケープする
+fn escape_command(command: &str) -> String {
+    command.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn unescape_command(command: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
 pub fn csv_writer(command: String, time: String, path: &str) -> std::io::Result<()> {
     let mut file = OpenOptions::new().append(true).create(true).open(path)?;
 
-    writeln!(file, "{},{}", command, time)?;
+    writeln!(file, "{},{}", escape_command(&command), time)?;
     Ok(())
 }
 
@@ -37,14 +59,44 @@ pub fn csv_reader(path: &str) -> io::Result<Vec<History>> {
 
     for line in reader.lines() {
         let line = line?;
-        let parts: Vec<&str> = line.split(',').collect();
-        if parts.len() == 2 {
+        // lines() が取り除かない末尾の \r (改行無しの最終行) も落とす
+        let line = line.trim_end_matches('\r');
+        // 時刻に , は含まれないので、コマンド中の , はそのまま残す
+        if let Some((command, time)) = line.rsplit_once(',') {
             records.push(History {
-                command: parts[0].to_string(),
-                time: parts[1].to_string(),
+                command: unescape_command(command),
+                time: time.to_string(),
             });
         }
     }
 
     Ok(records)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_command_fits_on_one_line() {
+        let command = "echo \"a\nb\" \\n";
+        let escaped = escape_command(command);
+        assert!(!escaped.contains('\n'));
+        assert_eq!(unescape_command(&escaped), command);
+    }
+
+    #[test]
+    fn history_round_trip() {
+        let path = std::env::temp_dir().join(format!("rsh-history-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        csv_writer("echo \"a\nb\"".to_string(), "t1".to_string(), path).unwrap();
+        csv_writer("echo x,y".to_string(), "t2".to_string(), path).unwrap();
+        let records = csv_reader(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].get_command(), "echo \"a\nb\"");
+        assert_eq!(records[1].get_command(), "echo x,y");
+        assert_eq!(records[1].get_time(), "t2");
+    }
+}
@@ -8,10 +8,12 @@ use crate::log::log_maneger::History;
 use colored::Colorize;
 use crossterm::{
     cursor::{MoveLeft, MoveRight, MoveTo, MoveToColumn, SetCursorStyle},
-    event::{read, Event, KeyCode, KeyEvent},
+    event::{
+        read, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
-    style::{Color, Print, SetForegroundColor},
-    terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
+    style::{Attribute, Color, Print, SetAttribute, SetForegroundColor},
+    terminal::{self, disable_raw_mode, enable_raw_mode, Clear, ClearType},
 };
 use error::error::{RshError, Status};
 use nix::{
@@ -24,18 +26,29 @@ use nix::{
     unistd::{close, execvp, fork, getpgrp, pipe, setpgid, tcsetpgrp, ForkResult},
 };
 use std::{
+    collections::{HashMap, HashSet},
     env,
-    ffi::CString,
+    ffi::{CStr, CString, OsStr, OsString},
     fs,
-    io::{stdout, Write},
+    io::{self, stdout, IsTerminal, Write},
+    os::unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
 };
 use unicode_segmentation::UnicodeSegmentation;
-use whoami::username;
+
+// プロンプトに表示するディレクトリの最大階層数
+const PROMPT_DIR_DEPTH: usize = 5;
+
+// 継続行のプロンプト (.rshenv の PS2 で変更できる)
+const CONTINUATION_PROMPT: &str = "> ";
 
 struct Prompt {
     username: String,
     pwd: String,
     utils: String,
+    // 描画済みのプロンプトから測った、色のエスケープを除いた表示幅
+    width: Option<usize>,
 }
 
 impl Prompt {
@@ -52,12 +65,22 @@ impl Prompt {
             pwd: {
                 let mut full_path = String::new();
                 for dir in pwd {
-                    full_path = format!("{}{}/", dir, full_path);
+                    full_path = format!("{}{}/", full_path, dir);
                 }
 
                 full_path
             },
             utils: format!(" [{}: {}] > ", return_code, mode_str),
+            width: None,
+        }
+    }
+
+    pub fn continuation(text: &str) -> Self {
+        Self {
+            username: String::new(),
+            pwd: String::new(),
+            utils: text.to_string(),
+            width: None,
         }
     }
 
@@ -72,10 +95,589 @@ impl Prompt {
     }
 
     pub fn len(&self) -> usize {
-        self.username.len() + self.pwd.len() + self.utils.len()
+        // 未描画なら各部分の表示幅から求める
+        self.width.unwrap_or_else(|| {
+            display_width(&self.username) + display_width(&self.pwd) + display_width(&self.utils)
+        })
+    }
+}
+
+fn char_width(c: char) -> usize {
+    // 制御文字と結合文字は幅を持たない
+    if c.is_control() || ('\u{0300}'..='\u{036F}').contains(&c) || c == '\u{200B}' {
+        return 0;
+    }
+    // 東アジアの全角文字や絵文字は2桁分を占める
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+fn write_error<W: Write>(out: &mut W, message: &str, color: bool) -> io::Result<()> {
+    // 診断メッセージはすべてここを通して "rsh: " を付ける
+    if color {
+        execute!(
+            out,
+            SetForegroundColor(Color::White),
+            Print("rsh: "),
+            SetForegroundColor(Color::Red),
+            Print(message),
+            SetForegroundColor(Color::White),
+            Print("\n"),
+        )?;
+    } else {
+        execute!(out, Print("rsh: "), Print(message), Print("\n"))?;
+    }
+    out.flush()
+}
+
+fn rsh_trace<W: Write>(
+    out: &mut W,
+    env_vars: &[(String, String)],
+    args: &[String],
+) -> io::Result<()> {
+    // set -x の表示は診断メッセージではないので "rsh: " も色も付けない
+    let quote = |word: &str| {
+        if word.is_empty() || word.contains(char::is_whitespace) {
+            format!("'{}'", word)
+        } else {
+            word.to_string()
+        }
+    };
+    let words: Vec<String> = env_vars
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, quote(value)))
+        .chain(args.iter().map(|arg| quote(arg)))
+        .collect();
+    writeln!(out, "+ {}", words.join(" "))?;
+    out.flush()
+}
+
+fn create_private_temp_file(prefix: &str, contents: &str) -> io::Result<PathBuf> {
+    // 他のユーザーが置いたファイルやシンボリックリンクを辿らないよう、
+    // 新しいファイルだけを自分しか読み書きできない権限で作る
+    let mut attempt = 0;
+    loop {
+        let path =
+            env::temp_dir().join(format!("{}-{}-{}.sh", prefix, std::process::id(), attempt));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            Err(err) => return Err(err),
+        }
     }
 }
 
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    // PATH の中で最初に見つかった同名のファイル
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+fn prefers_path(name: &str, forced_builtin: bool) -> bool {
+    // builtin で明示されない限り、PATH にあるコマンドを同名の組み込みより優先する
+    command::PATH_FIRST.contains(&name) && !forced_builtin && find_in_path(name).is_some()
+}
+
+fn runs_builtin(name: &str, forced_builtin: bool) -> bool {
+    command::BUILTINS.iter().any(|builtin| builtin.name == name)
+        && !prefers_path(name, forced_builtin)
+}
+
+fn shadowed_builtins(lookup: impl Fn(&str) -> Option<PathBuf>) -> Vec<(&'static str, PathBuf)> {
+    // PATH 上にある組み込みコマンドと同名の実行ファイル (実行されるのは組み込みの方)
+    command::BUILTINS
+        .iter()
+        .filter(|builtin| !command::PATH_FIRST.contains(&builtin.name))
+        .filter_map(|builtin| lookup(builtin.name).map(|path| (builtin.name, path)))
+        .collect()
+}
+
+fn passwd_home() -> Option<PathBuf> {
+    // HOME が無い場合はパスワードデータベースに登録されたホームディレクトリを使う
+    unsafe {
+        let passwd = libc::getpwuid(libc::getuid());
+        if passwd.is_null() || (*passwd).pw_dir.is_null() {
+            return None;
+        }
+        let dir = CStr::from_ptr((*passwd).pw_dir).to_bytes();
+        (!dir.is_empty()).then(|| PathBuf::from(OsStr::from_bytes(dir)))
+    }
+}
+
+fn username(system_name: Option<String>) -> String {
+    // 最小構成のコンテナなどでユーザー名が取れない場合は環境変数から補う
+    system_name
+        .filter(|name| !name.is_empty())
+        .or_else(|| env::var("USER").ok().filter(|name| !name.is_empty()))
+        .or_else(|| env::var("LOGNAME").ok().filter(|name| !name.is_empty()))
+        .unwrap_or_else(|| "user".to_string())
+}
+
+fn exit_code(status: WaitStatus) -> i32 {
+    // シグナルで終了した場合は 128 + シグナル番号 を終了ステータスにする
+    match status {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+        _ => 0,
+    }
+}
+
+fn history_key(editing_mode: EditingMode, code: KeyCode, modifiers: KeyModifiers) -> KeyCode {
+    // emacs方式の Ctrl-P / Ctrl-N は補完候補ではなく履歴を辿る
+    if editing_mode != EditingMode::Emacs || !modifiers.contains(KeyModifiers::CONTROL) {
+        return code;
+    }
+    match code {
+        KeyCode::Char('p') => KeyCode::Up,
+        KeyCode::Char('n') => KeyCode::Down,
+        code => code,
+    }
+}
+
+fn inner_word_range(line: &str, index: usize) -> Option<(usize, usize)> {
+    // index の文字を含む、空白か非空白が続く範囲 (文字単位、両端を含む)
+    let chars: Vec<char> = line.chars().collect();
+    let is_space = chars.get(index)?.is_whitespace();
+    let same = |c: &char| c.is_whitespace() == is_space;
+    let start = index - chars[..index].iter().rev().take_while(|c| same(c)).count();
+    let end = index + chars[index + 1..].iter().take_while(|c| same(c)).count();
+    Some((start, end))
+}
+
+fn delete_forward(buffer: &mut String, char_index: usize) {
+    // char_index 文字目を消す (行末では何もしない)
+    if let Some((index, _)) = buffer.char_indices().nth(char_index) {
+        buffer.remove(index);
+    }
+}
+
+fn exit_cursor_style(name: Option<&str>) -> SetCursorStyle {
+    // .rshenv の exit_cursor_style で終了時のカーソルの形を選ぶ (既定は端末側の設定)
+    match name {
+        Some("blinking-block") => SetCursorStyle::BlinkingBlock,
+        Some("steady-block") => SetCursorStyle::SteadyBlock,
+        Some("blinking-underscore") => SetCursorStyle::BlinkingUnderScore,
+        Some("steady-underscore") => SetCursorStyle::SteadyUnderScore,
+        Some("blinking-bar") => SetCursorStyle::BlinkingBar,
+        Some("steady-bar") => SetCursorStyle::SteadyBar,
+        _ => SetCursorStyle::DefaultUserShape,
+    }
+}
+
+fn rshenv_statements(data: &str) -> impl Iterator<Item = &str> {
+    // 空行と # で始まるコメント行は読み飛ばす
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn read_stdin_line() -> Result<Option<String>, RshError> {
+    // 子プロセスが残りの入力を読めるように、バッファせず一文字ずつ読む
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        match nix::unistd::read(libc::STDIN_FILENO, &mut byte) {
+            Ok(0) if line.is_empty() => return Ok(None),
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) => line.push(byte[0]),
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(err) => return Err(RshError::new(&err.to_string())),
+        }
+    }
+    // CRLF で書かれたスクリプトの \r がコマンドに混ざらないようにする
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(String::from_utf8_lossy(&line).to_string()))
+}
+
+fn cpu_times() -> (Duration, Duration) {
+    // シェル自身と終了した子プロセスのユーザー時間・システム時間の合計
+    let mut user = Duration::ZERO;
+    let mut sys = Duration::ZERO;
+    for who in [libc::RUSAGE_SELF, libc::RUSAGE_CHILDREN] {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(who, &mut usage) } == 0 {
+            user += Duration::new(
+                usage.ru_utime.tv_sec as u64,
+                usage.ru_utime.tv_usec as u32 * 1000,
+            );
+            sys += Duration::new(
+                usage.ru_stime.tv_sec as u64,
+                usage.ru_stime.tv_usec as u32 * 1000,
+            );
+        }
+    }
+    (user, sys)
+}
+
+fn format_duration(duration: Duration) -> String {
+    // bash の time と同じ 0m0.000s 形式
+    let seconds = duration.as_secs_f64();
+    format!("{}m{:.3}s", (seconds / 60.0) as u64, seconds % 60.0)
+}
+
+fn format_elapsed(duration: Duration) -> String {
+    // プロンプト用の短い形式 (3.4s / 2m5s)
+    let seconds = duration.as_secs_f64();
+    if seconds < 60.0 {
+        format!("{:.1}s", seconds)
+    } else {
+        format!("{}m{}s", duration.as_secs() / 60, duration.as_secs() % 60)
+    }
+}
+
+fn display_width(text: &str) -> usize {
+    // ANSIエスケープシーケンスを読み飛ばして表示幅を数える
+    let mut width = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            width += char_width(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: 終端文字 (0x40-0x7E) まで
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC: BEL か ST (ESC \) まで
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    width
+}
+
+fn percent_encode_path(path: &Path) -> String {
+    // URLに使えない文字はバイト単位で %XX にする
+    let mut encoded = String::new();
+    for byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(*byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// クォートとエスケープの状態を一文字ずつ追う (行の解析はすべてこれを通す)
+#[derive(Default)]
+struct QuoteScanner {
+    // 開いているクォートの種類
+    quote: Option<char>,
+    // 直前がバックスラッシュか
+    escaped: bool,
+}
+
+impl QuoteScanner {
+    fn step(&mut self, c: char) -> bool {
+        // c を読み進め、クォートの外でエスケープもされていない文字なら true を返す
+        // (クォート文字とバックスラッシュ自身は false)
+        if self.escaped {
+            self.escaped = false;
+            false
+        } else if c == '\\' && self.quote != Some('\'') {
+            // シングルクォート内ではエスケープしない
+            self.escaped = true;
+            false
+        } else if let Some(q) = self.quote {
+            if c == q {
+                self.quote = None;
+            }
+            false
+        } else if c == '"' || c == '\'' {
+            self.quote = Some(c);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+fn is_line_complete(line: &str) -> bool {
+    // 閉じられていないクォート・括弧や行末のバックスラッシュがあれば未完結
+    let (quote, escaped, open_brackets) = scan_line_state(line);
+    quote.is_none() && !escaped && open_brackets == 0
+}
+
+fn scan_line_state(line: &str) -> (Option<char>, bool, usize) {
+    // 行末の時点で開いているクォート、エスケープ中かどうか、閉じていない括弧の数を返す
+    let mut scanner = QuoteScanner::default();
+    let mut brackets: Vec<char> = Vec::new();
+    // コメント中のクォートや括弧は数えない (コメントは改行まで)
+    let mut in_comment = false;
+    let mut word_start = true;
+
+    for c in line.chars() {
+        if in_comment {
+            in_comment = c != '\n';
+            word_start = !in_comment;
+            continue;
+        }
+        let unquoted = scanner.step(c);
+        if unquoted && c == '#' && word_start {
+            in_comment = true;
+        } else if unquoted && c == '(' {
+            brackets.push(')');
+        } else if unquoted && c == '{' {
+            brackets.push('}');
+        } else if unquoted && brackets.last() == Some(&c) {
+            brackets.pop();
+        }
+        // エスケープされた空白の後の # は単語の先頭ではない
+        word_start = unquoted && c.is_whitespace();
+    }
+
+    (scanner.quote, scanner.escaped, brackets.len())
+}
+
+fn split_last_word(search_string: &str) -> (&str, &str) {
+    // 補完対象の最後の単語と、それより前の部分に分ける
+    let word_start = search_string.rfind(' ').map_or(0, |i| i + 1);
+    search_string.split_at(word_start)
+}
+
+fn next_completion_word(buffer: &str, candidate: &str) -> String {
+    // 候補のうち入力済みの部分より後ろを、次の単語の終わりまで取り出す
+    let Some(suffix) = candidate.strip_prefix(buffer) else {
+        return String::new();
+    };
+    let parts = rsh_split_line(suffix.to_string());
+    let end = parts
+        .iter()
+        .position(|part| !part.is_empty())
+        .unwrap_or(parts.len() - 1);
+    parts[..=end].join(" ")
+}
+
+fn rsh_split_line(line: String) -> Vec<String> {
+    let mut scanner = QuoteScanner::default();
+    let mut buffer = String::new();
+    let mut r_vec = Vec::new();
+
+    for c in line.chars() {
+        // クォートやエスケープはそのまま残し、クォートの外のスペースで区切る
+        if scanner.step(c) && c == ' ' {
+            // スペースごとに区切るので、連続したスペースは空の要素として残り
+            // " " で連結すると元の入力と同じ並びに戻る
+            r_vec.push(buffer.clone());
+            buffer.clear();
+        } else {
+            buffer.push(c);
+        }
+    }
+    r_vec.push(buffer);
+    r_vec
+}
+
+fn rsh_strip_comment(line: &str) -> String {
+    // クォートの外で単語の先頭にある # から行末まではコメント
+    let mut scanner = QuoteScanner::default();
+    let mut word_start = true;
+
+    for (i, c) in line.char_indices() {
+        let unquoted = scanner.step(c);
+        if unquoted && c == '#' && word_start {
+            return line[..i].trim_end().to_string();
+        }
+        // エスケープされた空白の後の # は単語の先頭ではない
+        word_start = unquoted && c.is_whitespace();
+    }
+
+    line.to_string()
+}
+
+fn rsh_history_word(command: &str, index: usize) -> Option<String> {
+    // 連続したスペースによる空の要素は単語として数えない (usize::MAX は最後の単語)
+    let words: Vec<String> = rsh_split_line(command.to_string())
+        .into_iter()
+        .filter(|word| !word.is_empty())
+        .collect();
+    if index == usize::MAX {
+        return words.last().cloned();
+    }
+    words.get(index).cloned()
+}
+
+fn unquote_word(word: &str) -> (String, bool) {
+    // クォートとエスケープを取り除き、クォートを含んでいたかどうかと共に返す
+    let mut unquoted = String::new();
+    let mut quoted = false;
+    let mut quote: Option<char> = None;
+    let mut chars = word.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                quoted = true;
+            }
+            (Some(q), _) if c == q => quote = None,
+            // シングルクォート内は全て文字通り
+            (Some('\''), _) => unquoted.push(c),
+            // ダブルクォート内では一部の文字だけエスケープできる
+            (Some(_), '\\') => match chars.next_if(|next| "\"\\$`".contains(*next)) {
+                Some(next) => unquoted.push(next),
+                None => unquoted.push(c),
+            },
+            (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    unquoted.push(next);
+                }
+            }
+            _ => unquoted.push(c),
+        }
+    }
+
+    (unquoted, quoted)
+}
+
+fn prompt_dirs(current_dir: &Path, home: Option<&Path>) -> Vec<String> {
+    // ホームディレクトリ以下は ~ から表示する
+    let mut now_dir: Vec<String> = match home.and_then(|home| current_dir.strip_prefix(home).ok()) {
+        Some(relative) => std::iter::once("~".to_string())
+            .chain(
+                relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().to_string()),
+            )
+            .collect(),
+        None => current_dir
+            .components()
+            .map(|component| match component {
+                // ルートは後ろに付く / で表す
+                Component::RootDir => String::new(),
+                _ => component.as_os_str().to_string_lossy().to_string(),
+            })
+            .collect(),
+    };
+
+    // 深すぎるパスは先頭と末尾を残して途中を … で省略する
+    if now_dir.len() > PROMPT_DIR_DEPTH {
+        let tail = now_dir.split_off(now_dir.len() - (PROMPT_DIR_DEPTH - 2));
+        now_dir.truncate(1);
+        now_dir.push("…".to_string());
+        now_dir.extend(tail);
+    }
+
+    now_dir
+}
+
+fn write_rshenv_variable(rshenv_path: &Path, name: &str, value: &str) -> Result<(), RshError> {
+    // .rshenv は一行に一つの設定なので、改行を含む値は書き込めない
+    if value.contains(['\n', '\r']) {
+        return Err(RshError::new(&format!(
+            "rshenv: {}: cannot save a value containing a line break",
+            name
+        )));
+    }
+    let data = fs::read_to_string(rshenv_path).unwrap_or_default();
+    let new_line = if value.is_empty() {
+        format!("setenv {}", name)
+    } else {
+        format!("setenv {} {}", name, value)
+    };
+    // 同じ行が既にあれば何もしない
+    if data.lines().any(|line| line == new_line) {
+        return Ok(());
+    }
+
+    // 同じ変数の古い値は置き換える
+    let mut lines: Vec<&str> = data
+        .lines()
+        .filter(|line| {
+            line.strip_prefix("setenv ")
+                .map(|rest| rest.split_once(' ').map_or(rest, |(old_name, _)| old_name))
+                != Some(name)
+        })
+        .collect();
+    lines.push(&new_line);
+    fs::write(rshenv_path, format!("{}\n", lines.join("\n")))
+        .map_err(|_| RshError::new("Failed to write rshenv"))
+}
+
+fn needs_confirmation(patterns: Option<&str>, line: &str) -> bool {
+    // confirm_patterns に書かれたいずれかで始まるコマンドは確認が必要
+    let Some(patterns) = patterns else {
+        return false;
+    };
+    let line = line.trim_start();
+    patterns
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| {
+            line.strip_prefix(pattern)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+        })
+}
+
+fn rsh_split_assignments(args: Vec<String>) -> (Vec<(String, String)>, Vec<String>) {
+    // コマンドの前に置かれた NAME=value をそのコマンド専用の環境変数として切り出す
+    let mut assignments = Vec::new();
+    let mut rest = args.into_iter().peekable();
+
+    while let Some(arg) = rest.peek() {
+        let Some((name, value)) = arg.split_once('=') else {
+            break;
+        };
+        let is_name = name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_name {
+            break;
+        }
+        assignments.push((name.to_string(), value.to_string()));
+        rest.next();
+    }
+
+    (assignments, rest.collect())
+}
+
 #[derive(PartialEq, Clone, Copy)]
 enum Mode {
     Nomal,
@@ -83,6 +685,13 @@ enum Mode {
     Input,
 }
 
+// 行編集の方式 (vim風のモード切り替え or 常に入力状態)
+#[derive(PartialEq, Clone, Copy)]
+enum EditingMode {
+    Modal,
+    Emacs,
+}
+
 struct Buffer {
     buffer: String,
 }
@@ -95,41 +704,80 @@ impl Buffer {
     }
 }
 
+// 読み込んだ .rshrc のディレクトリと、上書きした環境変数の元の値
+type DirectoryRc = (PathBuf, Vec<(String, Option<String>)>);
+
 #[derive()]
 struct Rsh {
     prompt: Prompt,
     buffer: Buffer,
+    // 継続行で入力済みの部分
+    continuation: String,
     env_database: Vec<String>,
+    config_database: HashMap<String, String>,
     history_database: Vec<History>,
     command_database: Vec<String>,
+    // カレントディレクトリのファイル名 (読み込むたびに置き換える)
+    directory_database: Vec<String>,
+    // complete で登録したコマンドごとの引数の補完候補
+    completion_database: HashMap<String, Vec<String>>,
+    directory_stack: Vec<String>,
+    directory_rc: Option<DirectoryRc>,
+    options: command::set::ShellOptions,
     return_code: i32,
+    // 直前に入力したコマンドの実行にかかった時間
+    last_duration: Option<Duration>,
     exists_rshenv: bool,
     now_mode: Mode,
+    editing_mode: EditingMode,
+    color_enabled: bool,
+    interactive: bool,
+    // --login で起動したログインシェルかどうか
+    login: bool,
     cursor_x: usize,
     char_count: usize,
+    completion_index: usize,
 }
 
 impl Rsh {
     fn open_profile(&self, path: &str) -> Result<String, RshError> {
-        let home_dir = env::current_dir()
-            .unwrap()
-            .into_os_string()
-            .into_string()
-            .map_err(|_| env::var("HOME"))
-            .map_err(|_| env::var("."))
-            .map_err(|_| RshError::new("Failed to get HOME directory"))?;
-        Ok(format!("{}/{}", home_dir, path))
-        /*
-         */
-    }
-
-    fn eprintln(&self, message: &str) {
-        let mut stderr = std::io::stderr();
-        execute!(stderr, Print(message), Print("\n"))
-            .map_err(|_| RshError::new("Failed to print error message"))
-            .unwrap();
+        let home_dir = self
+            .get_home_dir()
+            .ok_or_else(|| RshError::new("Failed to get HOME directory"))?;
+        Ok(format!("{}/{}", home_dir.display(), path))
+    }
+
+    fn history_path(&self) -> Result<String, RshError> {
+        // HISTFILE (環境変数か .rshenv の設定) があれば履歴をそこに保存する
+        let histfile = env::var("HISTFILE")
+            .ok()
+            .or_else(|| self.config_database.get("HISTFILE").cloned())
+            .filter(|path| !path.is_empty());
+        match histfile {
+            Some(path) => {
+                let (home, rest) = self.rsh_expand_tilde(&path)?;
+                Ok(format!("{}{}", home, rest))
+            }
+            None => self.open_profile(".rsh_history"),
+        }
+    }
+
+    fn rsh_dbg<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let counts = [
+            format!("command_database: {}", self.command_database.len()),
+            format!("history_database: {}", self.history_database.len()),
+            format!("env_database: {}", self.env_database.len()),
+        ];
+        let entries = self.env_database.iter().map(|entry| format!("  {}", entry));
+        for line in counts.into_iter().chain(entries) {
+            write_error(out, &line, self.color_enabled)?;
+        }
+        Ok(())
+    }
 
-        std::io::stdout().flush().unwrap();
+    fn error(&self, message: &str) {
+        // シェル自身の診断メッセージは "rsh: " を付けて標準エラーに出す
+        let _ = write_error(&mut std::io::stderr(), message, self.color_enabled);
     }
 
     fn get_executable_commands(&mut self) {
@@ -151,8 +799,15 @@ impl Rsh {
                     }
                 }
             }
-            self.command_database.sort();
         }
+        // 組み込みコマンドも補完候補に含める
+        self.command_database.extend(
+            command::BUILTINS
+                .iter()
+                .map(|builtin| builtin.name.to_string()),
+        );
+        self.command_database.sort();
+        self.command_database.dedup();
     }
 
     fn get_directory_contents(&mut self, path: &str) {
@@ -170,7 +825,7 @@ impl Rsh {
             }
         }
         contents.sort();
-        self.command_database.splice(0..0, contents);
+        self.directory_database = contents;
     }
 
     fn get_rshenv_contents(&mut self) -> Result<(), RshError> {
@@ -179,37 +834,98 @@ impl Rsh {
         //self.println(&rshenv_path.clone());
         let data =
             fs::read_to_string(&rshenv_path).map_err(|_| RshError::new("Failed to open rshenv"))?;
-        self.env_database = data.lines().map(|line| line.to_string()).collect();
+        self.env_database.clear();
+        self.config_database.clear();
+        self.completion_database.clear();
+        for line in rshenv_statements(&data) {
+            // 環境変数の行は起動時にだけ反映する
+            if line.starts_with("setenv ") {
+                continue;
+            }
+            // complete git=add,commit,push のようにコマンドごとの補完候補を登録する
+            if let Some((command, candidates)) = line
+                .strip_prefix("complete ")
+                .and_then(|rest| rest.split_once('='))
+            {
+                self.completion_database.insert(
+                    command.trim().to_string(),
+                    candidates
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|candidate| !candidate.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                );
+                continue;
+            }
+            // key=value の行は設定として扱う
+            match line.split_once('=') {
+                Some((key, value)) => {
+                    self.config_database
+                        .insert(key.trim().to_string(), value.trim().to_string());
+                }
+                None => self.env_database.push(line.to_string()),
+            }
+        }
+        self.editing_mode = match self.config_database.get("editing_mode").map(String::as_str) {
+            Some("emacs") => EditingMode::Emacs,
+            _ => EditingMode::Modal,
+        };
         self.exists_rshenv = true;
         Ok(())
     }
 
+    fn apply_rshenv_variables(&self) -> Result<(), RshError> {
+        let rshenv_path = self.open_profile(".rshenv")?;
+        let data =
+            fs::read_to_string(&rshenv_path).map_err(|_| RshError::new("Failed to open rshenv"))?;
+
+        for line in rshenv_statements(&data) {
+            if let Some(rest) = line.strip_prefix("setenv ") {
+                let (name, value) = rest.split_once(' ').unwrap_or((rest, ""));
+                command::setenv::rsh_setenv(&mut stdout(), &[name.to_string(), value.to_string()])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn save_rshenv_variable(&self, name: &str, value: &str) -> Result<(), RshError> {
+        if self.config_database.get("auto_save").map(String::as_str) != Some("true") {
+            return Ok(());
+        }
+        let rshenv_path = self.open_profile(".rshenv")?;
+        write_rshenv_variable(Path::new(&rshenv_path), name, value)
+    }
+
     fn get_rshhistory_contents(&mut self) -> Result<(), RshError> {
-        let history_path = self.open_profile(".rsh_history")?;
+        let history_path = self.history_path()?;
 
-        self.history_database =
-            csv_reader(&history_path).map_err(|_| RshError::new("Failed to get history path"))?;
+        // 履歴ファイルは最初の書き込み時に作られるので、存在しなければ空として扱う
+        self.history_database = match csv_reader(&history_path) {
+            Ok(history) => history,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(_) => return Err(RshError::new("Failed to get history path")),
+        };
         Ok(())
     }
 
+    fn get_home_dir(&self) -> Option<PathBuf> {
+        env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from)
+            .or_else(passwd_home)
+    }
+
     fn get_current_dir_as_vec(&self) -> Vec<String> {
         let current_dir = std::env::current_dir().unwrap();
-        let path = current_dir.as_path();
-        let mut now_dir: Vec<String> = path
-            .components()
-            .map(|component| component.as_os_str().to_string_lossy().to_string())
-            .collect();
-
-        if now_dir.len() > 2 {
-            now_dir.remove(0);
-            now_dir.remove(0);
-            now_dir.remove(0);
-        }
-
-        now_dir
+        prompt_dirs(&current_dir, self.get_home_dir().as_deref())
     }
 
-    fn set_prompt_color(&self, color_code: String) -> Result<(), RshError> {
+    fn set_prompt_color<W: Write>(&self, out: &mut W, color_code: String) -> Result<(), RshError> {
+        // 色を使わない設定なら何も出力しない
+        if !self.color_enabled {
+            return Ok(());
+        }
         if color_code.len() != 7 || !color_code.starts_with('#') {
             return Err(RshError::new("Invalid color code"));
         }
@@ -221,14 +937,16 @@ impl Rsh {
         let b = u8::from_str_radix(&color_code[5..7], 16)
             .map_err(|_| RshError::new("Invalid blue value"))?;
 
-        let mut stdout = stdout();
-        execute!(stdout, SetForegroundColor(Color::Rgb { r, g, b }))
+        execute!(out, SetForegroundColor(Color::Rgb { r, g, b }))
             .map_err(|_| RshError::new("Failed to set color"))?;
 
         Ok(())
     }
 
     fn get_mode_string(&self) -> &str {
+        if self.editing_mode == EditingMode::Emacs {
+            return "E";
+        }
         match self.now_mode {
             Mode::Nomal => "N",
             Mode::Input => "I",
@@ -237,64 +955,251 @@ impl Rsh {
         }
     }
 
-    fn set_prompt(&mut self) -> Result<(), RshError> {
-        let mut stdout = stdout();
+    fn build_prompt(&self) -> Prompt {
+        if !self.continuation.is_empty() {
+            return Prompt::continuation(&self.continuation_prompt());
+        }
+        Prompt::new(
+            username(whoami::fallible::username().ok()),
+            self.get_current_dir_as_vec(),
+            self.return_code,
+            self.now_mode,
+        )
+    }
+
+    fn set_prompt<W: Write>(&mut self, out: &mut W) -> Result<(), RshError> {
+        // 表示幅を測るため一度バッファに描画してから出力する
+        let mut rendered = Vec::new();
+        self.render_prompt(&mut rendered)?;
+        out.write_all(&rendered)
+            .and_then(|_| out.flush())
+            .map_err(|_| RshError::new("Failed to print prompt"))?;
+
+        self.prompt = self.build_prompt();
+        let width = display_width(&String::from_utf8_lossy(&rendered));
+        self.prompt.width = Some(width);
+
+        // 時間のかかったコマンドの後は右端に実行時間を表示する
+        if let Some(took) = self.duration_segment() {
+            let columns = terminal::size().map_or(0, |(columns, _)| columns as usize);
+            if width + took.len() < columns {
+                execute!(
+                    out,
+                    MoveToColumn((columns - took.len()) as u16),
+                    Print(&took),
+                    MoveToColumn(width as u16),
+                )
+                .map_err(|_| RshError::new("Failed to print prompt"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn duration_segment(&self) -> Option<String> {
+        // .rshenv の prompt_duration_threshold (秒) を超えたときだけ表示する
+        if !self.continuation.is_empty() {
+            return None;
+        }
+        let threshold: f64 = self
+            .config_database
+            .get("prompt_duration_threshold")?
+            .parse()
+            .ok()?;
+        let duration = self.last_duration?;
+        (duration.as_secs_f64() > threshold).then(|| format!("took {}", format_elapsed(duration)))
+    }
+
+    fn continuation_prompt(&self) -> String {
+        // 前後の空白を残したい場合は PS2="... " のようにクォートで囲む
+        match self.config_database.get("PS2") {
+            Some(ps2) => {
+                let unquoted = ps2
+                    .strip_prefix('"')
+                    .and_then(|ps2| ps2.strip_suffix('"'))
+                    .or_else(|| {
+                        ps2.strip_prefix('\'')
+                            .and_then(|ps2| ps2.strip_suffix('\''))
+                    });
+                unquoted.unwrap_or(ps2).to_string()
+            }
+            None => CONTINUATION_PROMPT.to_string(),
+        }
+    }
+
+    fn set_continuation_prompt<W: Write>(&self, out: &mut W) -> Result<(), RshError> {
+        self.set_prompt_color(out, "#d1d1d1".to_string())?;
+        execute!(
+            out,
+            MoveToColumn(0),
+            Clear(ClearType::UntilNewLine),
+            Print(self.continuation_prompt())
+        )
+        .map_err(|_| RshError::new("Failed to print prompt"))
+    }
+
+    fn render_prompt<W: Write>(&self, out: &mut W) -> Result<(), RshError> {
+        // 継続行では二次プロンプトだけを表示する
+        if !self.continuation.is_empty() {
+            return self.set_continuation_prompt(out);
+        }
         // ui ----------------------------------------------------
         // Set the prompt color
         if self.exists_rshenv {
             // Theme
             // 環境変数設定ファイルが存在する
-            self.set_prompt_color("#AC6683".to_string())?;
+            self.set_prompt_color(out, "#AC6683".to_string())?;
         } else {
             // Theme
-            self.set_prompt_color("#A61602".to_string())?;
+            self.set_prompt_color(out, "#A61602".to_string())?;
         }
         execute!(
-            stdout,
+            out,
             MoveToColumn(0),
             Clear(ClearType::UntilNewLine),
-            Print(username().bold()),
+            Print(username(whoami::fallible::username().ok()).bold()),
             Print(" "),
         )
         .map_err(|_| RshError::new("Failed to print directory"))?;
 
         // Theme
-        self.set_prompt_color("#d1d1d1".to_string())?;
+        self.set_prompt_color(out, "#d1d1d1".to_string())?;
 
         // Display the current directory in the prompt
         let dir_s = self.get_current_dir_as_vec();
         for dir in dir_s {
-            execute!(stdout, Print(dir), Print("/"))
+            execute!(out, Print(dir), Print("/"))
                 .map_err(|_| RshError::new("Failed to print directory"))?;
         }
 
         // Theme
-        self.set_prompt_color("#f8f8f8".to_string())?;
-        execute!(stdout, Print(" [".to_string())).unwrap();
-        self.set_prompt_color("#589F62".to_string())?;
-        execute!(stdout, Print(self.return_code)).unwrap();
-        self.set_prompt_color("#fafafa".to_string())?;
-        execute!(stdout, Print(": ".to_string())).unwrap();
+        self.set_prompt_color(out, "#f8f8f8".to_string())?;
+        execute!(out, Print(" [".to_string())).unwrap();
+        self.set_prompt_color(out, "#589F62".to_string())?;
+        execute!(out, Print(self.return_code)).unwrap();
+        self.set_prompt_color(out, "#fafafa".to_string())?;
+        execute!(out, Print(": ".to_string())).unwrap();
 
         match self.now_mode {
             // Theme
-            Mode::Input => self.set_prompt_color("#218587".to_string())?,
-            Mode::Nomal => self.set_prompt_color("#589F62".to_string())?,
-            Mode::Visual => self.set_prompt_color("#E9B42C".to_string())?,
+            Mode::Input => self.set_prompt_color(out, "#218587".to_string())?,
+            Mode::Nomal => self.set_prompt_color(out, "#589F62".to_string())?,
+            Mode::Visual => self.set_prompt_color(out, "#E9B42C".to_string())?,
         }
-        execute!(stdout, Print(self.get_mode_string())).unwrap();
+        execute!(out, Print(self.get_mode_string())).unwrap();
 
         // Theme
-        self.set_prompt_color("#fafafa".to_string())?;
-        execute!(stdout, Print("] > ")).unwrap();
+        self.set_prompt_color(out, "#fafafa".to_string())?;
+        execute!(out, Print("] > ")).unwrap();
 
         //std::io::stdout().flush().unwrap();
         // --------------------------------------------------------
         Ok(())
     }
 
+    fn update_directory_rc(&mut self) {
+        // directory_rc=true のとき、移動先の .rshrc の変数を読み込み、離れたら元に戻す
+        if self.config_database.get("directory_rc").map(String::as_str) != Some("true") {
+            return;
+        }
+        let Ok(current_dir) = env::current_dir() else {
+            return;
+        };
+        let rc_path = current_dir.join(".rshrc");
+        if let Some((dir, _)) = &self.directory_rc {
+            // 読み込んだディレクトリの配下にいる間はそのまま
+            if *dir == current_dir || (current_dir.starts_with(dir) && !rc_path.is_file()) {
+                return;
+            }
+        }
+        if let Some((_, saved)) = self.directory_rc.take() {
+            for (name, value) in saved {
+                match value {
+                    Some(value) => env::set_var(name, value),
+                    None => env::remove_var(name),
+                }
+            }
+        }
+
+        let Ok(data) = fs::read_to_string(&rc_path) else {
+            return;
+        };
+        // 安全のため directory_rc_allow に書かれたディレクトリのものだけを読み込む
+        let allowed = self
+            .config_database
+            .get("directory_rc_allow")
+            .is_some_and(|dirs| {
+                dirs.split(',')
+                    .filter_map(|dir| fs::canonicalize(dir.trim()).ok())
+                    .any(|dir| dir == current_dir)
+            });
+        if !allowed {
+            self.error(&format!(
+                "{}: not loaded (add the directory to directory_rc_allow)",
+                rc_path.display()
+            ));
+            return;
+        }
+
+        let mut saved: Vec<(String, Option<String>)> = Vec::new();
+        for line in data.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // setenv NAME VALUE と NAME=value の二つの書き方を受け付ける
+            let assignment = match line.strip_prefix("setenv ") {
+                Some(rest) => rest
+                    .trim()
+                    .split_once(' ')
+                    .map(|(name, value)| (name, value.trim()))
+                    .or(Some((rest.trim(), ""))),
+                None => line.split_once('='),
+            };
+            let Some((name, value)) = assignment else {
+                continue;
+            };
+            if name.is_empty() || name.contains(['=', ' ', '\0']) {
+                continue;
+            }
+            if !saved.iter().any(|(saved_name, _)| saved_name == name) {
+                saved.push((name.to_string(), env::var(name).ok()));
+            }
+            env::set_var(name, value);
+        }
+        self.directory_rc = Some((current_dir, saved));
+    }
+
+    fn report_current_dir<W: Write>(&self, out: &mut W) {
+        // terminal_title=true なら端末に現在のディレクトリを通知する (OSC 7 と OSC 0)
+        if self
+            .config_database
+            .get("terminal_title")
+            .map(String::as_str)
+            != Some("true")
+        {
+            return;
+        }
+        let Ok(current_dir) = env::current_dir() else {
+            return;
+        };
+        let host = whoami::fallible::hostname().unwrap_or_default();
+        let title = self.get_current_dir_as_vec().join("/");
+        let _ = execute!(
+            out,
+            Print(format!(
+                "\x1b]7;file://{}{}\x1b\\",
+                host,
+                percent_encode_path(&current_dir)
+            )),
+            Print(format!("\x1b]0;{}\x07", title))
+        );
+    }
+
     fn set_mode(&mut self, mode: Mode) {
-        self.now_mode = mode;
+        // emacs方式では Nomal や Visual に入らず、常に入力状態のまま
+        self.now_mode = match self.editing_mode {
+            EditingMode::Emacs => Mode::Input,
+            EditingMode::Modal => mode,
+        };
     }
 
     fn ignore_tty_signals(&self) {
@@ -315,115 +1220,310 @@ impl Rsh {
         }
     }
 
+    fn get_filtered_commands(&self, search_string: &str) -> Vec<String> {
+        // 履歴 (新しい順)、カレントディレクトリ、利用可能なコマンドの順に
+        // search_stringで始まるものを集め、重複は最初の一つだけ残す
+        let mut seen = HashSet::new();
+        let mut filtered_commands: Vec<String> = self
+            .history_database
+            .iter()
+            .rev()
+            .map(|history| history.get_command().as_str())
+            .chain(self.directory_database.iter().map(String::as_str))
+            .chain(self.command_database.iter().map(String::as_str))
+            .filter(|command| command.starts_with(search_string))
+            .filter(|command| seen.insert(*command))
+            .map(str::to_string)
+            .collect();
+
+        // もしもコマンドが見つからなかった場合、環境変数を利用して参照しなおす
+        if filtered_commands.is_empty() {
+            for env_path in &self.env_database {
+                // command_databaseの中からenv_path/search_stringで始まるものを取得
+                let matches = self.command_database.iter().filter(|command| {
+                    let command_path = format!("{}/{}", env_path, search_string);
+                    command.starts_with(search_string) || command_path.starts_with(search_string)
+                });
+                // 上記を配列に変換
+                filtered_commands = matches.map(|s| s.to_string()).collect();
+            }
+        }
+
+        filtered_commands
+    }
+
+    fn accept_completion_word(&mut self) {
+        let filtered_commands = self.get_completion_candidates(&self.buffer.buffer);
+        let Some(candidate) = filtered_commands.get(self.completion_index) else {
+            return;
+        };
+        let word = next_completion_word(&self.buffer.buffer, candidate);
+        self.buffer.buffer.push_str(&word);
+        self.set_cursor_char(self.buffer.buffer.chars().count());
+        // 残りの部分も同じ候補で表示し続ける
+        self.completion_index = self
+            .get_completion_candidates(&self.buffer.buffer)
+            .iter()
+            .position(|command| command == candidate)
+            .unwrap_or(0);
+    }
+
+    fn get_history_matches(&self, prefix: &str) -> Vec<String> {
+        // prefix で始まる履歴を新しい順に、重複を除いて返す
+        let mut seen = HashSet::new();
+        self.history_database
+            .iter()
+            .rev()
+            .map(|history| history.get_command().as_str())
+            .filter(|command| command.starts_with(prefix))
+            .filter(|command| seen.insert(*command))
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn get_registered_completions(&self, search_string: &str, command: &str) -> Vec<String> {
+        let (line_head, word) = split_last_word(search_string);
+        self.completion_database
+            .get(command)
+            .map(|candidates| {
+                candidates
+                    .iter()
+                    .filter(|candidate| candidate.starts_with(word))
+                    .map(|candidate| format!("{}{}", line_head, candidate))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn get_directory_completions(&self, search_string: &str) -> Vec<String> {
+        // 最後の単語をディレクトリ部分と名前の先頭部分に分ける
+        let (line_head, word) = split_last_word(search_string);
+        let (dir, name_prefix) = match word.rfind('/') {
+            Some(i) => word.split_at(i + 1),
+            None => ("", word),
+        };
+
+        let mut directories: Vec<String> = fs::read_dir(if dir.is_empty() { "./" } else { dir })
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+                    .filter(|name| name.starts_with(name_prefix))
+                    // 隠しディレクトリは . を入力したときだけ候補にする
+                    .filter(|name| !name.starts_with('.') || name_prefix.starts_with('.'))
+                    .collect()
+            })
+            .unwrap_or_default();
+        directories.sort();
+        directories
+            .into_iter()
+            .map(|name| format!("{}{}{}/", line_head, dir, name))
+            .collect()
+    }
+
+    fn get_completion_candidates(&self, search_string: &str) -> Vec<String> {
+        // 表示する候補と Tab で確定する候補は必ずこの一覧から選ぶ
+        let words = rsh_split_line(search_string.to_string());
+        match (words.first().map(String::as_str), words.len() > 1) {
+            // cd の引数はディレクトリだけを補完する
+            (Some("cd"), true) => self.get_directory_completions(search_string),
+            // complete で登録されたコマンドの引数は登録された候補から補完する
+            (Some(command), true) if self.completion_database.contains_key(command) => {
+                let candidates = self.get_registered_completions(search_string, command);
+                if candidates.is_empty() {
+                    self.get_filtered_commands(search_string)
+                } else {
+                    candidates
+                }
+            }
+            _ => self.get_filtered_commands(search_string),
+        }
+    }
+
     fn rsh_char_search(
         &self,
         search_string: String,
         counter: &mut usize,
     ) -> Result<String, RshError> {
-        let matches = self
-            .command_database
-            .iter()
-            .filter(|command| command.starts_with(&search_string));
+        let filtered_commands = self.get_completion_candidates(&search_string);
 
-        let history_matches: Vec<String> = self
-            .history_database
-            .iter()
-            .filter(|history| history.get_command().starts_with(&search_string))
-            .map(|history| history.get_command().to_string())
-            .collect();
-
-        let mut filtered_commands: Vec<String> =
-            history_matches.into_iter().map(|s| s.to_string()).collect();
-        filtered_commands.extend(matches.map(|s| s.to_string()));
+        if filtered_commands.is_empty() {
+            return Err(RshError::new("No command found"));
+        }
+        if filtered_commands.len() <= *counter {
+            *counter = filtered_commands.len() - 1;
+        }
+        let mut completion = filtered_commands[*counter].clone();
 
-        match filtered_commands.len() {
-            0 => {
-                return Err(RshError::new("No command found"));
-            }
-            _ => {
-                if filtered_commands.clone().len() <= *counter {
-                    *counter -= 1;
-                }
-                Ok(filtered_commands[*counter].clone())
+        // 候補が一つに決まる場合は区切り (ディレクトリなら / ) を付ける
+        if filtered_commands.len() == 1 && !completion.ends_with(['/', ' ']) {
+            if Path::new(&completion).is_dir() {
+                completion.push('/');
+            } else {
+                completion.push(' ');
             }
         }
+        Ok(completion)
     }
 
-    fn rsh_split_line(&self, line: String) -> Vec<String> {
-        let mut quote_flag = false;
-        let mut in_quote_buffer = String::new();
-        let mut buffer = String::new();
-        let mut r_vec = Vec::new();
-        let mut quote_start_index = 0;
-
-        for c in line.chars() {
-            if c == '"' {
-                match quote_flag {
-                    true => {
-                        //クォートに囲まれた文字列を挿入
-                        buffer.replace_range(quote_start_index.., &in_quote_buffer);
-
-                        //閉じるクォートを挿入
-                        buffer.push('"');
-                        in_quote_buffer.clear();
-                        quote_start_index = 0;
-                    }
-                    false => {
-                        //始めるクォート
-                        buffer.push('"');
-                        // クォートが閉じられた際に挿入される部分を記録
-                        quote_start_index = buffer.len();
+    fn rsh_expand_history(&self, line: &str) -> Result<String, RshError> {
+        // !n を履歴のn番目 (1始まり) のコマンドに置き換える
+        let mut expanded = String::new();
+        let mut chars = line.chars().peekable();
+        let mut scanner = QuoteScanner::default();
+
+        while let Some(c) = chars.next() {
+            // シングルクォート内とエスケープされた ! は展開しない
+            let expands = c == '!' && !scanner.escaped && scanner.quote != Some('\'');
+            if !expands {
+                scanner.step(c);
+                expanded.push(c);
+                continue;
+            }
+            match chars.peek() {
+                Some(next) if next.is_ascii_digit() => {
+                    let mut number = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        number.push(digit);
                     }
+                    let history = number
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|i| self.history_database.get(i))
+                        .ok_or_else(|| RshError::new(&format!("!{}: event not found", number)))?;
+                    expanded.push_str(history.get_command());
                 }
-                quote_flag = !quote_flag;
-            } else if c == ' ' && quote_flag != true {
-                // Bufferが空の場合はスペースを挿入する
-                r_vec.push(buffer.clone());
-                buffer.clear();
-            } else {
-                buffer.push(c);
-                match quote_flag {
-                    true => in_quote_buffer.push(c),
-                    false => {}
+                // !$ / !^ / !! / !!:n は直前のコマンドの単語に置き換える
+                Some('$' | '^' | '!') => {
+                    let designator = chars.next().unwrap_or_default();
+                    let mut event = format!("!{}", designator);
+                    let index = match designator {
+                        '$' => Some(usize::MAX),
+                        '^' => Some(1),
+                        _ if chars.peek() == Some(&':') => {
+                            chars.next();
+                            let mut number = String::new();
+                            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                                number.push(digit);
+                            }
+                            event = format!("!!:{}", number);
+                            Some(number.parse::<usize>().map_err(|_| {
+                                RshError::new(&format!("{}: bad word specifier", event))
+                            })?)
+                        }
+                        _ => None,
+                    };
+                    let previous = self
+                        .history_database
+                        .last()
+                        .ok_or_else(|| RshError::new(&format!("{}: event not found", event)))?
+                        .get_command()
+                        .to_string();
+                    let Some(index) = index else {
+                        expanded.push_str(&previous);
+                        continue;
+                    };
+                    expanded.push_str(
+                        &rsh_history_word(&previous, index).ok_or_else(|| {
+                            RshError::new(&format!("{}: bad word specifier", event))
+                        })?,
+                    );
                 }
+                _ => expanded.push(c),
             }
         }
-        r_vec.push(buffer.clone());
-        buffer.clear();
-        r_vec
+
+        Ok(expanded)
     }
 
-    fn rsh_launch(&mut self, args: Vec<String>) -> Result<Status, RshError> {
+    fn rsh_expand_tilde<'a>(&self, token: &'a str) -> Result<(String, &'a str), RshError> {
+        // 先頭の ~ / ~+ / ~- / ~N を HOME / PWD / OLDPWD / スタックのN番目に置き換え、
+        // 残りの部分と共に返す
+        let end = token.find('/').unwrap_or(token.len());
+        let (prefix, rest) = token.split_at(end);
+        let expanded = match prefix {
+            "~" => self
+                .get_home_dir()
+                .ok_or_else(|| RshError::new("~: HOME not set"))?
+                .to_string_lossy()
+                .to_string(),
+            "~+" => env::var("PWD")
+                .ok()
+                .or_else(|| {
+                    env::current_dir()
+                        .ok()
+                        .map(|dir| dir.to_string_lossy().to_string())
+                })
+                .ok_or_else(|| RshError::new("~+: PWD not set"))?,
+            "~-" => env::var("OLDPWD").map_err(|_| RshError::new("~-: OLDPWD not set"))?,
+            // ~N / ~+N / ~-N はディレクトリスタックのN番目
+            _ if prefix
+                .strip_prefix('~')
+                .map(|index| index.trim_start_matches(['+', '-']))
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())) =>
+            {
+                command::dirs::directory_stack_entry(&self.directory_stack, &prefix[1..])
+                    .map_err(|err| RshError::new(&format!("~{}", err.message)))?
+            }
+            _ => return Ok((String::new(), token)),
+        };
+        Ok((expanded, rest))
+    }
+
+    fn rsh_unquote_args(&self, tokens: Vec<String>) -> Result<Vec<String>, RshError> {
+        // チルダを展開し、クォートとエスケープを取り除いて引数にする
+        let mut args = Vec::new();
+
+        for token in tokens {
+            let (mut arg, rest) = self.rsh_expand_tilde(&token)?;
+            let (unquoted, quoted) = unquote_word(rest);
+            arg.push_str(&unquoted);
+
+            // 連続した空白で生じた空の要素は捨て、"" は空の引数として残す
+            if !arg.is_empty() || quoted {
+                args.push(arg);
+            }
+        }
+
+        Ok(args)
+    }
+
+    fn rsh_launch(
+        &mut self,
+        args: Vec<String>,
+        env_vars: Vec<(String, String)>,
+    ) -> Result<Status, RshError> {
         let pid = fork().map_err(|_| RshError::new("fork failed"))?;
         let (pipe_read, pipe_write) = pipe().unwrap();
 
         match pid {
             ForkResult::Parent { child } => {
-                setpgid(child, child).unwrap();
-                tcsetpgrp(0, child).unwrap();
+                // 子プロセスが既に exec 済みだと EACCES になるので失敗は無視する
+                let _ = setpgid(child, child);
+                // 端末に繋がっていない場合は端末の制御を渡さない
+                let _ = tcsetpgrp(0, child);
                 close(pipe_read).unwrap();
                 close(pipe_write).unwrap();
 
                 let wait_pid_result =
                     waitpid(child, None).map_err(|err| RshError::new(&format!("{}", err)));
 
-                tcsetpgrp(0, getpgrp()).unwrap();
+                let _ = tcsetpgrp(0, getpgrp());
 
                 match wait_pid_result {
-                    Ok(WaitStatus::Exited(_, return_code)) => {
-                        // ui
-                        self.return_code = return_code;
-                        Ok(Status::Success)
-                    }
-                    Ok(WaitStatus::Signaled(_, _, _)) => {
-                        println!("signaled");
+                    Ok(status) => {
+                        if let WaitStatus::Signaled(_, signal, _) = status {
+                            self.error(&format!("Terminated by {:?}", signal));
+                        }
+                        self.return_code = exit_code(status);
                         Ok(Status::Success)
                     }
                     Err(err) => {
-                        self.eprintln(&format!("rsh: {}", err.message));
+                        self.error(&err.message);
                         Ok(Status::Success)
                     }
-                    _ => Ok(Status::Success),
                 }
             }
             ForkResult::Child => {
@@ -453,6 +1553,11 @@ impl Rsh {
                 close(pipe_read).unwrap();
                 // ------------------------------------------
 
+                // コマンド専用の環境変数は子プロセスにだけ設定する
+                for (name, value) in env_vars {
+                    env::set_var(name, value);
+                }
+
                 // コマンドパース
                 let path = CString::new(args[0].to_string()).unwrap();
 
@@ -461,73 +1566,447 @@ impl Rsh {
                     .map(|s| CString::new(s.as_bytes()).unwrap())
                     .collect();
 
+                // #! 付きのスクリプトはカーネルがインタプリタを選んで実行する
                 execvp(&path, &c_args)
                     .map(|_| Status::Success)
-                    .map_err(|_| RshError::new(&format!("{} is not found", args[0])))
+                    .map_err(|err| match err {
+                        nix::Error::Sys(Errno::EACCES) => {
+                            RshError::new(&format!("{}: permission denied", args[0]))
+                        }
+                        nix::Error::Sys(Errno::ENOEXEC) => {
+                            RshError::new(&format!("{}: exec format error", args[0]))
+                        }
+                        _ => RshError::new(&format!("{} is not found", args[0])),
+                    })
 
                 // -------------
             }
         }
     }
 
+    fn confirm<W: Write>(&self, out: &mut W, line: &str) -> bool {
+        // y で実行、それ以外 (n や Esc) で中止
+        execute!(out, Print(format!("Run '{}'? [y/N] ", line))).unwrap();
+        enable_raw_mode().unwrap();
+        let answer = loop {
+            if let Event::Key(KeyEvent { code, .. }) = read().unwrap() {
+                break matches!(code, KeyCode::Char('y') | KeyCode::Char('Y'));
+            }
+        };
+        disable_raw_mode().unwrap();
+        execute!(out, Print("\n")).unwrap();
+        answer
+    }
+
+    fn rsh_write_history(&self, line: &str) -> Result<(), RshError> {
+        let time = chrono::Local::now()
+            .format(command::history::TIME_FORMAT)
+            .to_string();
+        let path = self.history_path()?;
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent)
+                .map_err(|err| RshError::new(&format!("history: {}: {}", parent.display(), err)))?;
+        }
+
+        csv_writer(line.to_string(), time, &path)
+            .map_err(|err| RshError::new(&format!("history: {}: {}", path, err)))
+    }
+
+    fn rsh_split_commands(&self, line: &str) -> Vec<String> {
+        // 貼り付けなどで複数行になった入力を、クォートや括弧の外の改行でコマンドごとに分ける
+        let mut commands = Vec::new();
+        let mut start = 0;
+        for (i, c) in line.char_indices() {
+            if c == '\n' && is_line_complete(&line[start..i]) {
+                commands.push(line[start..i].to_string());
+                start = i + 1;
+            }
+        }
+        commands.push(line[start..].to_string());
+        commands
+    }
+
+    fn rsh_run_line(&mut self, line: &str) -> Result<Status, RshError> {
+        for command in self.rsh_split_commands(line) {
+            if command.trim().is_empty() {
+                continue;
+            }
+            // 行末のコメントを取り除いてから実行可能な形式に分割
+            let args = match self.rsh_unquote_args(rsh_split_line(rsh_strip_comment(&command))) {
+                Ok(args) => args,
+                Err(err) => {
+                    self.error(&err.message);
+                    self.return_code = 1;
+                    Vec::new()
+                }
+            };
+            if let exit @ Status::Exit(_) = self.rsh_execute(args)? {
+                return Ok(exit);
+            }
+            // set -e: 失敗したら残りのコマンドを実行せずにその終了ステータスで終わる
+            if self.options.errexit && self.return_code != 0 {
+                return Ok(Status::Exit(self.return_code));
+            }
+        }
+        Ok(Status::Success)
+    }
+
+    fn rsh_paste<W: Write>(&mut self, out: &mut W, text: &str) {
+        // 貼り付けた改行では実行せず、それまでの行は継続行として溜めておく
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let tail = self.buffer.buffer.split_off(self.cursor_x);
+        let mut lines = text.split('\n');
+        self.buffer
+            .buffer
+            .push_str(lines.next().unwrap_or_default());
+        for line in lines {
+            // 確定した行は表示したまま次の行へ送る
+            let _ = self.set_prompt(out);
+            self.rsh_print(out, self.buffer.buffer.clone());
+            execute!(out, Print("\r\n")).unwrap();
+            self.continuation.push_str(&self.buffer.buffer);
+            self.continuation.push('\n');
+            self.buffer.buffer = line.to_string();
+        }
+        self.cursor_x = self.buffer.buffer.len();
+        self.char_count = self.buffer.buffer.chars().count();
+        self.buffer.buffer.push_str(&tail);
+    }
+
+    fn rsh_repeat(&mut self, count: usize, command: &[String]) -> Result<Status, RshError> {
+        // repeat N command...: コマンドをN回実行し、最後の終了ステータスを残す
+        self.return_code = 0;
+        for _ in 0..count {
+            if let exit @ Status::Exit(_) = self.rsh_execute(command.to_vec())? {
+                return Ok(exit);
+            }
+        }
+        Ok(Status::Success)
+    }
+
+    fn rsh_fc(&mut self) -> Result<Status, RshError> {
+        // 直前のコマンドを $VISUAL / $EDITOR で編集し、保存された内容を実行する
+        let Some(command) = self
+            .history_database
+            .iter()
+            .rev()
+            .map(|history| history.get_command().to_string())
+            .find(|command| command.split_whitespace().next() != Some("fc"))
+        else {
+            self.error("fc: no command in history");
+            self.return_code = 1;
+            return Ok(Status::Success);
+        };
+        let path = match create_private_temp_file("rsh-fc", &format!("{}\n", command)) {
+            Ok(path) => path,
+            Err(err) => {
+                self.error(&format!("fc: {}", err));
+                self.return_code = 1;
+                return Ok(Status::Success);
+            }
+        };
+
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        let mut args: Vec<String> = editor.split_whitespace().map(str::to_string).collect();
+        args.push(path.to_string_lossy().to_string());
+        // exec に失敗した子プロセスのエラーはそのまま返す
+        self.rsh_launch(args, Vec::new())?;
+
+        let edited = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+        if self.return_code != 0 {
+            self.error(&format!(
+                "fc: editor exited with status {}",
+                self.return_code
+            ));
+            return Ok(Status::Success);
+        }
+        let edited = match edited {
+            Ok(edited) => edited,
+            Err(err) => {
+                self.error(&format!("fc: {}: {}", path.display(), err));
+                self.return_code = 1;
+                return Ok(Status::Success);
+            }
+        };
+        let line = edited.trim_end_matches('\n');
+        if line.trim().is_empty() {
+            return Ok(Status::Success);
+        }
+
+        // 実際に実行したコマンドを表示して履歴に残す
+        println!("{}", line);
+        if let Err(err) = self.rsh_write_history(line) {
+            self.error(&err.message);
+        }
+        self.rsh_run_line(line)
+    }
+
+    fn rsh_time(&mut self, args: &[String]) -> Result<Status, RshError> {
+        // time command...: 実行にかかった時間を標準エラーに出す (終了ステータスはそのまま)
+        let (user_before, sys_before) = cpu_times();
+        let start = Instant::now();
+        let status = if args.is_empty() {
+            self.return_code = 0;
+            Status::Success
+        } else {
+            self.rsh_execute(args.to_vec())?
+        };
+        let real = start.elapsed();
+        let (user_after, sys_after) = cpu_times();
+
+        eprintln!();
+        eprintln!("real\t{}", format_duration(real));
+        eprintln!(
+            "user\t{}",
+            format_duration(user_after.saturating_sub(user_before))
+        );
+        eprintln!(
+            "sys\t{}",
+            format_duration(sys_after.saturating_sub(sys_before))
+        );
+        Ok(status)
+    }
+
+    fn rsh_rerun(&mut self) -> Result<Status, RshError> {
+        // 直前の履歴を表示してから再実行する (r 自身の履歴は飛ばす)
+        let Some(line) = self
+            .history_database
+            .iter()
+            .rev()
+            .map(|history| history.get_command().to_string())
+            .find(|command| command.split_whitespace().next() != Some("r"))
+        else {
+            self.error("r: no command in history");
+            self.return_code = 1;
+            return Ok(Status::Success);
+        };
+        // fc と同じく、実際に実行したコマンドを表示して履歴に残す
+        println!("{}", line);
+        if let Err(err) = self.rsh_write_history(&line) {
+            self.error(&err.message);
+        }
+        self.rsh_run_line(&line)
+    }
+
     fn rsh_execute(&mut self, args: Vec<String>) -> Result<Status, RshError> {
-        if let Option::Some(arg) = args.get(0) {
-            let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
-            let path = self.open_profile(".rsh_history")?;
-
-            csv_writer(args.join(" "), time, &path)
-                .map_err(|_| RshError::new("Failed to write history"))?;
-            return match arg.as_str() {
-                // cd: ディレクトリ移動の組み込みコマンド
-                "cd" =>
-                match
-                command::cd::rsh_cd(if let Option::Some(dir) = args.get(1) {
-                    dir
-                } else {
-                    execute!(stdout(), Print("\n")).unwrap();
-                    std::io::stdout().flush().unwrap();
-                    "./"
-                }) {
-                    Err(err) => {
-                        self.eprintln(&format!("Error: {}", err.message));
-                        Ok(Status::Success)
+        if !args.is_empty() {
+            let (env_vars, args) = rsh_split_assignments(args);
+            let Some(arg) = args.first() else {
+                // コマンドが無い代入は環境変数として設定する
+                for (name, value) in env_vars {
+                    if let Err(err) = self.save_rshenv_variable(&name, &value) {
+                        self.error(&err.message);
                     }
-                    _ => Ok(Status::Success),
-
+                    env::set_var(name, value);
                 }
-                ,
-                // ロゴ表示
-                "%logo" => command::logo::rsh_logo(),
-                // history: 履歴表示の組み込みコマンド
-                "%fl" => command::history::rsh_history(self.history_database.clone()).map(|_| Status::Success),
-                // exit: 終了用の組み込みコマンド
-                "exit" => command::exit::rsh_exit(),
-                // none: 何もなければコマンド実行
-                _ => self.rsh_launch(args),
+                self.return_code = 0;
+                return Ok(Status::Success);
             };
+            // builtin NAME ...: PATH を見ずに組み込みコマンドとして実行する
+            let forced_builtin = arg == "builtin";
+            let args = if forced_builtin {
+                match args.get(1) {
+                    None => {
+                        self.return_code = 0;
+                        return Ok(Status::Success);
+                    }
+                    Some(name) if !command::BUILTINS.iter().any(|b| b.name == name) => {
+                        self.error(&format!("builtin: {}: not a shell builtin", name));
+                        self.return_code = 1;
+                        return Ok(Status::Success);
+                    }
+                    Some(_) => args[1..].to_vec(),
+                }
+            } else {
+                args
+            };
+            let arg = &args[0];
+            // set -x なら展開後のコマンドを表示する
+            if self.options.xtrace {
+                let _ = rsh_trace(&mut io::stderr(), &env_vars, &args);
+            }
+
+            // 組み込みコマンドの前に置いた NAME=value は、そのコマンドの実行中だけ環境変数にする
+            // (time/repeat が起動する子プロセスにも引き継がれる)
+            if !env_vars.is_empty() && runs_builtin(arg, forced_builtin) {
+                let saved: Vec<(String, Option<OsString>)> = env_vars
+                    .iter()
+                    .map(|(name, _)| (name.clone(), env::var_os(name)))
+                    .collect();
+                for (name, value) in &env_vars {
+                    env::set_var(name, value);
+                }
+                let result = self.rsh_dispatch(args, Vec::new(), forced_builtin);
+                for (name, value) in saved {
+                    match value {
+                        Some(value) => env::set_var(name, value),
+                        None => env::remove_var(name),
+                    }
+                }
+                return result;
+            }
+            return self.rsh_dispatch(args, env_vars, forced_builtin);
         }
         Ok(Status::Success)
     }
 
-    pub fn rsh_print(&self, buffer: String) {
-        let space_counter = buffer.chars().filter(|&c| c == ' ').count();
-        let print_buf_parts: Vec<String> = self.rsh_split_line(buffer.clone()); //print_buf.split_whitespace().collect();
+    fn rsh_dispatch(
+        &mut self,
+        args: Vec<String>,
+        env_vars: Vec<(String, String)>,
+        forced_builtin: bool,
+    ) -> Result<Status, RshError> {
+        let arg = &args[0];
+        let result = match arg.as_str() {
+            // cd: ディレクトリ移動の組み込みコマンド
+            "cd" => {
+                // -P ならシンボリックリンクを解決する、-L (既定) なら論理パスのまま
+                let (physical, dir) = match args.get(1).map(String::as_str) {
+                    Some("-P") => (true, args.get(2)),
+                    Some("-L") => (false, args.get(2)),
+                    _ => (false, args.get(1)),
+                };
+                command::cd::rsh_cd(
+                    if let Option::Some(dir) = dir {
+                        dir
+                    } else {
+                        execute!(stdout(), Print("\n")).unwrap();
+                        std::io::stdout().flush().unwrap();
+                        "./"
+                    },
+                    physical,
+                )
+            }
+            // pushd/popd/dirs: ディレクトリスタックの組み込みコマンド
+            "pushd" => command::dirs::rsh_pushd(
+                &mut stdout(),
+                &mut self.directory_stack,
+                args.get(1).map(String::as_str),
+            ),
+            "popd" => command::dirs::rsh_popd(&mut stdout(), &mut self.directory_stack),
+            "dirs" => command::dirs::rsh_dirs(&mut stdout(), &self.directory_stack, &args[1..]),
+            // ロゴ表示
+            "%logo" | "logo" => command::logo::rsh_logo(&mut stdout()),
+            // history: 履歴表示の組み込みコマンド
+            "%fl" | "history" => command::history::rsh_history(
+                &mut stdout(),
+                self.history_database.clone(),
+                args.get(1).map(String::as_str),
+            )
+            .map(|_| Status::Success),
+            // printenv: 環境変数表示の組み込みコマンド
+            "printenv" => {
+                command::printenv::rsh_printenv(&mut stdout(), args.get(1).map(String::as_str))
+            }
+            // setenv: 環境変数設定の組み込みコマンド
+            "setenv" => {
+                command::setenv::rsh_setenv(&mut stdout(), &args[1..]).and_then(|status| {
+                    // auto_save=true なら .rshenv にも書き込む
+                    if let [name, value @ ..] = &args[1..] {
+                        self.save_rshenv_variable(name, value.first().map_or("", String::as_str))?;
+                    }
+                    Ok(status)
+                })
+            }
+            // set: シェルのオプションを切り替える組み込みコマンド
+            "set" => command::set::rsh_set(&mut stdout(), &args[1..], &mut self.options),
+            // PATH に同名のコマンドがあればそちらを使う (組み込みは無い環境向けの代わり)
+            name if prefers_path(name, forced_builtin) => return self.rsh_launch(args, env_vars),
+            // cat: ファイルを連結して表示する組み込みコマンド
+            "cat" => command::cat::rsh_cat(&mut stdout(), &args[1..]),
+            // sort: 行を並べ替える組み込みコマンド
+            "sort" => command::sort::rsh_sort(&mut stdout(), &args[1..]),
+            // grep: パターンを含む行だけを出力する組み込みコマンド
+            "grep" => command::grep::rsh_grep(&mut stdout(), &args[1..]),
+            // wc: 行数・単語数・バイト数を数える組み込みコマンド
+            "wc" => command::wc::rsh_wc(&mut stdout(), &args[1..]),
+            // repeat/r: コマンドの繰り返し・再実行 (終了ステータスは実行したコマンドのもの)
+            // (子プロセスで exec に失敗したときのエラーはそのまま返す必要がある)
+            "repeat" => match args.get(1).and_then(|n| n.parse::<usize>().ok()) {
+                Some(count) if args.len() > 2 => return self.rsh_repeat(count, &args[2..]),
+                _ => Err(RshError::new("repeat: usage: repeat N command")),
+            },
+            "r" => return self.rsh_rerun(),
+            // fc: 直前のコマンドをエディタで編集してから実行する
+            "fc" => return self.rsh_fc(),
+            // time: コマンドの実行時間を計測する
+            "time" => return self.rsh_time(&args[1..]),
+            // rehash: コマンド一覧を読み直す (-v なら PATH 上の同名コマンドを知らせる)
+            "rehash" => {
+                self.get_executable_commands();
+                if args.get(1).map(String::as_str) == Some("-v") {
+                    for (name, path) in shadowed_builtins(find_in_path) {
+                        self.error(&format!(
+                            "note: builtin '{}' shadows {}",
+                            name,
+                            path.display()
+                        ));
+                    }
+                }
+                Ok(Status::Success)
+            }
+            // %dbg: 補完に使う各データベースの件数を標準エラーに出す (デバッグ用、一覧には出さない)
+            "%dbg" => {
+                let _ = self.rsh_dbg(&mut io::stderr());
+                Ok(Status::Success)
+            }
+            // help: 組み込みコマンド一覧
+            "help" => command::help::rsh_help(&mut stdout(), args.get(1).map(String::as_str)),
+            // exit: 終了用の組み込みコマンド
+            "exit" => command::exit::rsh_exit(
+                &mut stdout(),
+                args.get(1).map(String::as_str),
+                self.return_code,
+            ),
+            // none: 何もなければコマンド実行
+            _ => return self.rsh_launch(args, env_vars),
+        };
+
+        // ディレクトリを移動したら .rshrc を読み込み直す
+        if result.is_ok() && matches!(arg.as_str(), "cd" | "pushd" | "popd") {
+            self.update_directory_rc();
+        }
+
+        // 組み込みコマンドの終了ステータスを反映
+        match result {
+            Ok(status) => {
+                self.return_code = 0;
+                Ok(status)
+            }
+            Err(err) => {
+                self.error(&err.message);
+                self.return_code = 1;
+                Ok(Status::Success)
+            }
+        }
+    }
+
+    pub fn rsh_print<W: Write>(&self, out: &mut W, buffer: String) {
+        let print_buf_parts: Vec<String> = rsh_split_line(buffer); //print_buf.split_whitespace().collect();
 
-        let mut tmp = 0;
         // 瓶覗 かめのぞき
         // コマンドの色
-        self.set_prompt_color("#457E7D".to_string()).unwrap();
-        for i in &print_buf_parts {
-            execute!(stdout(), Print(i)).unwrap();
-            if tmp < space_counter {
-                tmp += 1;
-                execute!(stdout(), Print(" ")).unwrap();
+        self.set_prompt_color(out, "#457E7D".to_string()).unwrap();
+        for (i, part) in print_buf_parts.iter().enumerate() {
+            execute!(out, Print(part)).unwrap();
+            if i < print_buf_parts.len() - 1 {
+                execute!(out, Print(" ")).unwrap();
                 // コマンド引数の色
-                self.set_prompt_color("#809E8A".to_string()).unwrap();
+                self.set_prompt_color(out, "#809E8A".to_string()).unwrap();
             }
         }
     }
 
+    fn redraw_line<W: Write>(&mut self, out: &mut W) {
+        // 端末の幅が変わると折り返し位置がずれるので、行を消してから描き直す
+        execute!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown)).unwrap();
+        let _ = self.set_prompt(out);
+        self.rsh_print(out, self.buffer.buffer.clone());
+    }
+
     pub fn get_string_at_cursor(&self, start_pos: usize) -> String {
         self.buffer
             .buffer
@@ -544,24 +2023,76 @@ impl Rsh {
             .collect()
     }
 
+    fn delete_selection(&mut self, start_pos: usize) {
+        self.buffer.buffer = self.get_string_at_cursor(start_pos);
+        // 削除した範囲の先頭にカーソルを置く (バイト位置は文字位置から求め直す)
+        let last_char = self.buffer.buffer.chars().count().saturating_sub(1);
+        self.set_cursor_char(start_pos.min(self.char_count).min(last_char));
+    }
+
+    fn set_cursor_char(&mut self, char_index: usize) {
+        // 文字単位の位置からバイト単位の位置を求める
+        let char_index = char_index.min(self.buffer.buffer.chars().count());
+        self.cursor_x = self
+            .buffer
+            .buffer
+            .char_indices()
+            .nth(char_index)
+            .map(|(i, _)| i)
+            .unwrap_or(self.buffer.buffer.len());
+        self.char_count = char_index;
+    }
+
+    fn delete_char_at_cursor(&mut self) {
+        delete_forward(&mut self.buffer.buffer, self.char_count);
+        self.set_cursor_char(self.char_count);
+    }
+
     fn initializations_cursor_value(&mut self) {
         self.cursor_x = self.buffer.buffer.len();
         self.char_count = self.buffer.buffer.chars().count();
     }
 
+    fn column_of(&self, char_index: usize) -> usize {
+        // バッファの char_index 文字目が表示される列 (全角文字は2列分)
+        self.prompt.len()
+            + self
+                .buffer
+                .buffer
+                .chars()
+                .take(char_index)
+                .map(char_width)
+                .sum::<usize>()
+    }
+
     fn initializations_cursor_view(&mut self, stdout: &mut std::io::Stdout) {
         // カーソルを行の最後尾に移動
-        let mut count = 0;
-        for (i, c) in self.buffer.buffer.chars().enumerate() {
-            if i >= self.char_count {
-                break;
-            }
-            count += 1;
-            if !c.is_ascii() {
-                count += 1;
-            }
-        }
-        execute!(stdout, MoveToColumn((self.prompt.len() + count) as u16)).unwrap();
+        execute!(stdout, MoveToColumn(self.column_of(self.char_count) as u16)).unwrap();
+    }
+
+    fn render_selection<W: Write>(&self, out: &mut W, start_pos: usize) {
+        // 選択範囲 (開始位置と現在位置を含む) を反転表示する
+        let from = start_pos.min(self.char_count);
+        let to = start_pos.max(self.char_count);
+        let selected: String = self
+            .buffer
+            .buffer
+            .chars()
+            .skip(from)
+            .take(to - from + 1)
+            .collect();
+        // 前回の反転表示を消すため行を描き直してから重ねる
+        execute!(out, MoveToColumn(self.prompt.len() as u16)).unwrap();
+        self.rsh_print(out, self.buffer.buffer.clone());
+        execute!(
+            out,
+            Clear(ClearType::UntilNewLine),
+            MoveToColumn(self.column_of(from) as u16),
+            SetAttribute(Attribute::Reverse),
+            Print(selected),
+            SetAttribute(Attribute::NoReverse),
+        )
+        .unwrap();
     }
     pub fn move_cursor_left(
         &mut self,
@@ -569,6 +2100,10 @@ impl Rsh {
         direction: &str,
         range_string: &mut String,
     ) {
+        // 行頭では左に動けない
+        if self.char_count == 0 {
+            return;
+        }
         // 相対移動
         // Bufferの文字列内でカーソルを移動させるため
         let char_len = self
@@ -635,6 +2170,10 @@ impl Rsh {
         direction: &str,
         range_string: &mut String,
     ) {
+        // 行末では右に動けない
+        if self.char_count >= self.buffer.buffer.chars().count() {
+            return;
+        }
         // 相対移動
         // Bufferの文字列内でカーソルを移動させるため
         let char_len = self
@@ -713,11 +2252,13 @@ impl Rsh {
     pub fn rsh_move_cursor(&mut self) {
         let mut stdout = stdout();
         let mut range_string = String::new();
-        let start_pos = self.char_count;
+        let mut start_pos = self.char_count;
         //let start_cursor_x = self.cursor_x;
         // 範囲選択がどの方向に進んでいるか
         let mut direction: &str = "";
         let mut direction_set = false;
+        // i の後に w 以外が来たときは、そのキーを普通の入力として次に処理する
+        let mut pending_event: Option<Event> = None;
 
         // 初期値
         if self.now_mode == Mode::Nomal {
@@ -736,16 +2277,23 @@ impl Rsh {
                     direction = "right";
                 }
             }
-            self.initializations_cursor_view(&mut stdout);
             // デザイン部分
+            if self.now_mode == Mode::Visual {
+                self.render_selection(&mut stdout, start_pos);
+            }
+            self.initializations_cursor_view(&mut stdout);
 
             // キー入力の取得
+            let event = pending_event.take().unwrap_or_else(|| read().unwrap());
+            if let Event::Resize(_, _) = event {
+                self.redraw_line(&mut stdout);
+            }
             if let Event::Key(KeyEvent {
                 code,
                 modifiers: _,
                 kind: _,
                 state: _,
-            }) = read().unwrap()
+            }) = event
             {
                 // "( )" ← この文字があると不具合が発生する
                 match code {
@@ -763,10 +2311,30 @@ impl Rsh {
                             self.move_cursor_right(&mut stdout, direction, &mut range_string);
                         }
                     }
+                    // Visualモードでの iw はカーソル位置の単語 (空白の上なら空白の並び) を選択する
+                    KeyCode::Char('i') if self.now_mode == Mode::Visual => match read().unwrap() {
+                        Event::Key(KeyEvent {
+                            code: KeyCode::Char('w'),
+                            ..
+                        }) => {
+                            if let Some((start, end)) =
+                                inner_word_range(&self.buffer.buffer, self.char_count)
+                            {
+                                start_pos = start;
+                                self.set_cursor_char(end);
+                            }
+                        }
+                        event => pending_event = Some(event),
+                    },
                     KeyCode::Char('i') => {
                         self.now_mode = Mode::Input;
                         break;
                     }
+                    // Visualモードでの Enter は選択を解除してNomalモードに戻る (実行はしない)
+                    KeyCode::Enter if self.now_mode == Mode::Visual => {
+                        self.now_mode = Mode::Nomal;
+                        break;
+                    }
                     KeyCode::Char('v') => {
                         self.now_mode = Mode::Visual;
                         break;
@@ -783,9 +2351,7 @@ impl Rsh {
                             execute!(stdout, Print(" ")).unwrap();
                             execute!(stdout, MoveLeft(1)).unwrap();
                         }*/
-                        self.buffer.buffer = self.get_string_at_cursor(start_pos);
-                        self.cursor_x = self.buffer.buffer.len();
-                        self.char_count = self.buffer.buffer.chars().count();
+                        self.delete_selection(start_pos);
                         self.now_mode = Mode::Nomal;
                         break;
                     }
@@ -804,6 +2370,60 @@ impl Rsh {
         stdout.flush().unwrap();
     }
 
+    fn load_startup_files(&mut self) -> Result<Status, RshError> {
+        // 起動時に設定と保存された環境変数を読み込む
+        if self.get_rshenv_contents().is_err() {
+            self.exists_rshenv = false;
+        }
+        if let Err(err) = self.apply_rshenv_variables() {
+            if self.exists_rshenv {
+                self.error(&err.message);
+            }
+        }
+        if !self.login {
+            return Ok(Status::Success);
+        }
+
+        // ログインシェルでは .rsh_profile の各行をコマンドとして一度だけ実行する
+        let profile_path = self.open_profile(".rsh_profile")?;
+        let Ok(data) = fs::read_to_string(&profile_path) else {
+            return Ok(Status::Success);
+        };
+        for line in rshenv_statements(&data) {
+            if let exit @ Status::Exit(_) = self.rsh_run_line(line)? {
+                return Ok(exit);
+            }
+        }
+        Ok(Status::Success)
+    }
+
+    pub fn rsh_run_stdin(&mut self) -> Result<Status, RshError> {
+        // 端末でない標準入力から一行ずつ読み、行編集なしで実行する
+        if let exit @ Status::Exit(_) = self.load_startup_files()? {
+            return Ok(exit);
+        }
+
+        while let Some(input) = read_stdin_line()? {
+            // 行が完結していなければ次の行に続ける
+            let line = format!("{}{}", self.continuation, input);
+            if !is_line_complete(&line) {
+                let (_, escaped, _) = scan_line_state(&line);
+                self.continuation = if escaped {
+                    line[..line.len() - 1].to_string()
+                } else {
+                    format!("{}\n", line)
+                };
+                continue;
+            }
+            self.continuation.clear();
+
+            if let exit @ Status::Exit(_) = self.rsh_run_line(&line)? {
+                return Ok(exit);
+            }
+        }
+        Ok(Status::Exit(self.return_code))
+    }
+
     pub fn rsh_loop(&mut self) -> Result<Status, RshError> {
         let mut stdout = stdout();
 
@@ -819,20 +2439,20 @@ impl Rsh {
         self.cursor_x = self.buffer.buffer.len();
         self.char_count = self.buffer.buffer.chars().count();
 
+        if let exit @ Status::Exit(_) = self.load_startup_files()? {
+            return Ok(exit);
+        }
         loop {
+            // emacs方式では常に入力状態 (source で切り替えた場合もここで戻す)
+            self.set_mode(self.now_mode);
             enable_raw_mode().unwrap();
+            // 貼り付けた改行で実行されないようにする (子プロセスが戻すこともあるので毎回)
+            let _ = execute!(stdout, EnableBracketedPaste);
 
-            let _ = self.set_prompt();
-            let prompt = Prompt::new(
-                username(),
-                self.get_current_dir_as_vec(),
-                self.return_code,
-                self.now_mode,
-            );
-
-            self.prompt = prompt;
+            self.report_current_dir(&mut stdout);
+            let _ = self.set_prompt(&mut stdout);
 
-            self.rsh_print(self.buffer.buffer.clone());
+            self.rsh_print(&mut stdout, self.buffer.buffer.clone());
 
             match self.now_mode {
                 Mode::Nomal => {
@@ -853,6 +2473,8 @@ impl Rsh {
                     let mut stack_buffer = String::new();
                     let mut tab_counter = 0;
                     let mut esc_pressed = false;
+                    // ↑↓で辿っている履歴の検索 (最初に↑を押した時点の入力, 何件目か)
+                    let mut history_search: Option<(String, usize)> = None;
 
                     enable_raw_mode().unwrap();
 
@@ -861,28 +2483,122 @@ impl Rsh {
                         self.initializations_cursor_view(&mut stdout);
 
                         // キー入力の取得
+                        let event = read().unwrap();
+                        if let Event::Resize(_, _) = event {
+                            self.redraw_line(&mut stdout);
+                        }
+                        if let Event::Paste(text) = &event {
+                            self.rsh_paste(&mut stdout, text);
+                            self.completion_index = 0;
+                        }
                         if let Event::Key(KeyEvent {
                             code,
-                            modifiers: _,
+                            modifiers,
                             kind: _,
                             state: _,
-                        }) = read().unwrap()
+                        }) = event
                         {
+                            let code = history_key(self.editing_mode, code, modifiers);
+                            // ↑↓以外のキーで履歴の検索をやめる
+                            if !matches!(code, KeyCode::Up | KeyCode::Down) {
+                                history_search = None;
+                            }
                             match code {
+                                // 入力済みの文字列で始まる履歴だけを辿る
+                                KeyCode::Up => {
+                                    let (prefix, index) = match history_search.take() {
+                                        Some((prefix, index)) => (prefix, index + 1),
+                                        None => {
+                                            let _ = self.get_rshhistory_contents();
+                                            (self.buffer.buffer.clone(), 0)
+                                        }
+                                    };
+                                    let matches = self.get_history_matches(&prefix);
+                                    if let Some(last) = matches.len().checked_sub(1) {
+                                        let index = index.min(last);
+                                        self.buffer.buffer = matches[index].clone();
+                                        self.set_cursor_char(self.buffer.buffer.chars().count());
+                                        history_search = Some((prefix, index));
+                                    }
+                                    self.completion_index = 0;
+                                }
+                                KeyCode::Down => {
+                                    if let Some((prefix, index)) = history_search.take() {
+                                        // 一番新しい履歴より先に進むと入力していた文字列に戻る
+                                        self.buffer.buffer = match index.checked_sub(1) {
+                                            Some(index) => {
+                                                let matches = self.get_history_matches(&prefix);
+                                                history_search = Some((prefix, index));
+                                                matches[index].clone()
+                                            }
+                                            None => prefix,
+                                        };
+                                        self.set_cursor_char(self.buffer.buffer.chars().count());
+                                    }
+                                    self.completion_index = 0;
+                                }
+                                // emacs方式ではモードを切り替えない
+                                KeyCode::Esc if self.editing_mode == EditingMode::Emacs => {}
                                 KeyCode::Esc => {
-                                    self.now_mode = Mode::Nomal;
+                                    self.set_mode(Mode::Nomal);
                                     esc_pressed = true;
                                     break;
                                 }
+                                // カーソル移動
+                                KeyCode::Char('a') if modifiers.contains(KeyModifiers::CONTROL) => {
+                                    self.set_cursor_char(0);
+                                }
+                                KeyCode::Home => {
+                                    self.set_cursor_char(0);
+                                }
+                                KeyCode::Char('e') if modifiers.contains(KeyModifiers::CONTROL) => {
+                                    self.set_cursor_char(self.buffer.buffer.chars().count());
+                                }
+                                KeyCode::End => {
+                                    self.set_cursor_char(self.buffer.buffer.chars().count());
+                                }
+                                KeyCode::Char('b') if modifiers.contains(KeyModifiers::CONTROL) => {
+                                    self.set_cursor_char(self.char_count.saturating_sub(1));
+                                }
+                                KeyCode::Left => {
+                                    self.set_cursor_char(self.char_count.saturating_sub(1));
+                                }
+                                // 行末での → / Ctrl+→ / Alt+→ は補完候補の次の単語だけを確定する
+                                KeyCode::Right
+                                    if self.char_count >= self.buffer.buffer.chars().count() =>
+                                {
+                                    self.accept_completion_word();
+                                }
+                                KeyCode::Char('f') if modifiers.contains(KeyModifiers::CONTROL) => {
+                                    self.set_cursor_char(self.char_count + 1);
+                                }
+                                KeyCode::Right => {
+                                    self.set_cursor_char(self.char_count + 1);
+                                }
+                                // カーソル位置の文字を削除 (カーソルは動かさない)
+                                KeyCode::Delete => {
+                                    self.delete_char_at_cursor();
+                                    self.completion_index = 0;
+                                }
+                                // 補完候補の切り替え (emacs方式では履歴の移動になるので Tab で選ぶ)
+                                KeyCode::Char('n') if modifiers.contains(KeyModifiers::CONTROL) => {
+                                    self.completion_index += 1;
+                                }
+                                KeyCode::Char('p') if modifiers.contains(KeyModifiers::CONTROL) => {
+                                    self.completion_index = self.completion_index.saturating_sub(1);
+                                }
                                 KeyCode::Tab => {
                                     if !pushed_tab {
                                         // 現時点で入力されている文字のバックアップ
                                         stack_buffer = self.buffer.buffer.clone();
+                                        // 表示中の候補から補完を始める
+                                        tab_counter = self.completion_index;
                                     }
                                     // コマンドDBの取得
                                     self.get_executable_commands();
                                     self.get_directory_contents("./");
-                                    self.get_rshhistory_contents().unwrap();
+                                    // 読めない場合は前回までの履歴で補完する
+                                    let _ = self.get_rshhistory_contents();
 
                                     // 予測されるコマンドを取得
                                     if let Ok(autocomplete) =
@@ -896,10 +2612,12 @@ impl Rsh {
 
                                     pushed_tab = true;
                                     tab_counter += 1;
+                                    self.completion_index = 0;
                                 }
                                 KeyCode::Enter => {
                                     self.cursor_x = 0;
                                     self.char_count = 0;
+                                    self.completion_index = 0;
                                     break;
                                 }
                                 KeyCode::Char(' ') => {
@@ -908,8 +2626,10 @@ impl Rsh {
                                     pushed_tab = false;
                                     self.cursor_x += 1;
                                     self.char_count += 1;
+                                    self.completion_index = 0;
                                 }
                                 _ => {
+                                    self.completion_index = 0;
                                     self.buffer.buffer = match code {
                                         KeyCode::Backspace => {
                                             // カーソルがバッファの範囲内にある場合
@@ -970,55 +2690,25 @@ impl Rsh {
 
                         // Inputモードから離脱
                         if self.now_mode != Mode::Input {
-                            if self.char_count > 0 {
-                                self.move_cursor_left(&mut stdout, "left", &mut String::new());
-                            }
+                            self.move_cursor_left(&mut stdout, "left", &mut String::new());
                             continue;
                         }
-                        // コマンド実行履歴の中からbufferで始まるものを取得
-                        let history_matches: Vec<String> = self
-                            .history_database
-                            .iter()
-                            .filter(|history| {
-                                history.get_command().starts_with(&self.buffer.buffer)
-                            })
-                            .map(|history| history.get_command().to_string())
-                            .collect();
-
-                        // 利用可能なコマンドの中からbufferで始まるものを取得
-                        let matches = self
-                            .command_database
-                            .iter()
-                            .filter(|command| command.starts_with(&self.buffer.buffer));
-
-                        // 上記を配列に変換
-                        let mut filtered_commands: Vec<String> =
-                            history_matches.into_iter().map(|s| s.to_string()).collect();
-                        filtered_commands.extend(matches.map(|s| s.to_string()));
-
-                        // もしもコマンドが見つからなかった場合、環境変数を利用して参照しなおす
-                        if filtered_commands.len() == 0 {
-                            for env_path in self.env_database.clone() {
-                                // command_databaseの中からenv_path/bufferで始まるものを取得
-                                let matches = self.command_database.iter().filter(|command| {
-                                    let command_path =
-                                        format!("{}/{}", env_path, self.buffer.buffer);
-                                    command.starts_with(&self.buffer.buffer)
-                                        || command_path.starts_with(&self.buffer.buffer)
-                                });
-                                // 上記を配列に変換
-                                filtered_commands = matches.map(|s| s.to_string()).collect();
-                            }
+                        // 補完候補を取得
+                        let filtered_commands = self.get_completion_candidates(&self.buffer.buffer);
+                        if !filtered_commands.is_empty() {
+                            self.completion_index =
+                                self.completion_index.min(filtered_commands.len() - 1);
                         }
 
-                        let _ = self.set_prompt();
+                        let _ = self.set_prompt(&mut stdout);
 
                         let print_buf_parts: Vec<String> =
-                            self.rsh_split_line(self.buffer.buffer.clone()); //print_buf.split_whitespace().collect();
+                            rsh_split_line(self.buffer.buffer.clone()); //print_buf.split_whitespace().collect();
 
                         // 瓶覗 かめのぞき
                         // コマンドの色
-                        self.set_prompt_color("#457E7D".to_string()).unwrap();
+                        self.set_prompt_color(&mut stdout, "#457E7D".to_string())
+                            .unwrap();
                         // コマンド・コマンド引数ともに表示
                         for (i, part) in print_buf_parts.iter().enumerate() {
                             // 一つのコマンド
@@ -1026,19 +2716,23 @@ impl Rsh {
 
                             if i < print_buf_parts.len() - 1 {
                                 execute!(stdout, Print(" ")).unwrap();
-                                self.set_prompt_color("#AC6383".to_string()).unwrap();
+                                self.set_prompt_color(&mut stdout, "#AC6383".to_string())
+                                    .unwrap();
                             }
                         }
 
-                        // 補完されるコマンドがある場合描写する
-                        if filtered_commands.len() > 0 {
-                            // 部分的に一致しているコマンドの先頭の要素からbufferから先を取得
-                            let print_buf_suffix = self.rsh_split_line(
-                                filtered_commands[0][self.buffer.buffer.len()..].to_string(),
-                            );
+                        // 補完されるコマンドがある場合描写する (色が無いと入力と区別できないので省く)
+                        if let Some(candidate) = filtered_commands
+                            .get(self.completion_index)
+                            .filter(|_| self.color_enabled)
+                        {
+                            // 選択中の候補からbufferから先を取得
+                            let print_buf_suffix =
+                                rsh_split_line(candidate[self.buffer.buffer.len()..].to_string());
 
                             // コマンド補完表示の色
-                            self.set_prompt_color("#938274".to_string()).unwrap();
+                            self.set_prompt_color(&mut stdout, "#938274".to_string())
+                                .unwrap();
 
                             let mut print_length = 0;
                             // コマンド・コマンド引数ともに表示
@@ -1061,7 +2755,7 @@ impl Rsh {
 
                     //self.cursor_x = 0;
                     //self.char_count = 0;
-                    self.set_prompt_color("#ECE1B4".to_string())?;
+                    self.set_prompt_color(&mut stdout, "#ECE1B4".to_string())?;
                     execute!(stdout, MoveToColumn(0)).unwrap();
 
                     // Inputモードから離脱
@@ -1071,28 +2765,75 @@ impl Rsh {
                     }
                     execute!(stdout, Print("\n")).unwrap();
 
-                    // 入力を実行可能な形式に分割
-                    let args = self.rsh_split_line(self.buffer.buffer.clone());
+                    // 行が完結していなければ次の行に続ける
+                    let line = format!("{}{}", self.continuation, self.buffer.buffer);
+                    if !is_line_complete(&line) {
+                        let (_, escaped, _) = scan_line_state(&line);
+                        self.continuation = if escaped {
+                            // 行末のバックスラッシュは改行ごと取り除く
+                            line[..line.len() - 1].to_string()
+                        } else {
+                            format!("{}\n", line)
+                        };
+                        self.buffer.buffer = String::new();
+                        continue;
+                    }
+                    self.continuation.clear();
 
                     // 実行可能なコマンド一覧を取得
                     self.get_executable_commands();
 
                     // 履歴ファイルが存在するか？
                     if let Err(err) = self.get_rshhistory_contents() {
-                        self.eprintln(&format!("Error: {}", err.message));
+                        self.error(&err.message);
                     }
                     // 環境変数ファイルが存在するか？
                     if let Err(_) = self.get_rshenv_contents() {
-                        //self.eprintln(&format!("Error: {}", err.message));
+                        //self.error(&err.message);
                         self.exists_rshenv = false;
                     }
 
                     self.buffer.buffer = String::new();
+
+                    // 履歴を展開
+                    let line = match self.rsh_expand_history(&line) {
+                        Ok(expanded) => {
+                            if expanded != line {
+                                println!("{}", expanded);
+                            }
+                            expanded
+                        }
+                        Err(err) => {
+                            self.error(&err.message);
+                            self.return_code = 1;
+                            continue;
+                        }
+                    };
+
+                    // 危険なコマンドは実行前に確認する
+                    let patterns = self.config_database.get("confirm_patterns");
+                    if needs_confirmation(patterns.map(String::as_str), &line)
+                        && !self.confirm(&mut stdout, &line)
+                    {
+                        continue;
+                    }
+
+                    // 入力された形のまま履歴に残す
+                    // (書き込めなくてもシェルは続ける)
+                    if !line.trim().is_empty() {
+                        if let Err(err) = self.rsh_write_history(&line) {
+                            self.error(&err.message);
+                        }
+                    }
+
                     // 分割したコマンドを実行
-                    match self.rsh_execute(args) {
+                    let start = Instant::now();
+                    let result = self.rsh_run_line(&line);
+                    self.last_duration = Some(start.elapsed());
+                    match result {
                         Ok(status) => match status {
                             Status::Success => continue,
-                            exit @ Status::Exit => return Ok(exit),
+                            exit @ Status::Exit(_) => return Ok(exit),
                         },
                         err @ Err(_) => return err,
                     };
@@ -1112,16 +2853,34 @@ impl Rsh {
 
     pub fn new() -> Self {
         Self {
-            prompt: Prompt::new(username(), vec!["".to_string()], 0, Mode::Nomal),
+            prompt: Prompt::new(
+                username(whoami::fallible::username().ok()),
+                vec!["".to_string()],
+                0,
+                Mode::Nomal,
+            ),
             buffer: Buffer::new(),
+            continuation: String::new(),
             env_database: Vec::new(),
+            config_database: HashMap::new(),
             history_database: Vec::new(),
             command_database: Vec::new(),
+            directory_database: Vec::new(),
+            completion_database: HashMap::new(),
+            directory_stack: Vec::new(),
+            directory_rc: None,
+            options: command::set::ShellOptions::default(),
             return_code: 0,
+            last_duration: None,
             exists_rshenv: false,
             now_mode: Mode::Nomal,
+            editing_mode: EditingMode::Modal,
+            color_enabled: true,
+            interactive: true,
+            login: false,
             cursor_x: 0,
             char_count: 0,
+            completion_index: 0,
         }
     }
 }
@@ -1129,34 +2888,965 @@ impl Drop for Rsh {
     fn drop(&mut self) {
         // 必要なクリーンアップをここで実行
         // drop以外の名前を定義することはできない
-        execute!(
-            stdout(),
-            SetForegroundColor(Color::White),
-            SetCursorStyle::DefaultUserShape
-        )
-        .unwrap();
+        if !self.interactive {
+            return;
+        }
+        if self.color_enabled {
+            execute!(stdout(), SetForegroundColor(Color::White)).unwrap();
+        }
+        let style = exit_cursor_style(
+            self.config_database
+                .get("exit_cursor_style")
+                .map(String::as_str),
+        );
+        execute!(stdout(), style).unwrap();
+        let _ = execute!(stdout(), DisableBracketedPaste);
     }
 }
 
 fn main() {
-    let mut rsh = Rsh::new();
-    let code = rsh.rsh_loop();
+    // 端末を操作する前に情報表示だけのオプションを処理する
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--version" | "-V" => {
+                println!("rsh {}", env!("CARGO_PKG_VERSION"));
+                return;
+            }
+            "--help" | "-h" => {
+                println!("Usage: rsh [options]");
+                println!();
+                println!("Options:");
+                println!("  -l, --login    run as a login shell (reads .rsh_profile)");
+                println!("  --no-color     disable colored output (also NO_COLOR)");
+                println!("  -V, --version  print the version and exit");
+                println!("  -h, --help     print this help and exit");
+                return;
+            }
+            _ => {}
+        }
+    }
+
+    // 端末に繋がっていなければ行編集をせず標準入力から読んで実行する
+    let interactive = std::io::stdin().is_terminal() && stdout().is_terminal();
+
+    // NO_COLOR (空でない値) か --no-color が指定されているか、端末でなければ色を付けない
+    let color_enabled = env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && !env::args().skip(1).any(|arg| arg == "--no-color")
+        && interactive;
+    if !color_enabled {
+        colored::control::set_override(false);
+    }
+
+    // 終了前に端末の状態を戻すため、Rshはここで破棄する
+    let code = {
+        let mut rsh = Rsh::new();
+        rsh.color_enabled = color_enabled;
+        rsh.interactive = interactive;
+        rsh.login = env::args()
+            .skip(1)
+            .any(|arg| arg == "--login" || arg == "-l");
+        if interactive {
+            rsh.rsh_loop()
+        } else {
+            rsh.rsh_run_stdin()
+        }
+    };
     match code {
         Err(err) => {
-            if let Err(e) = execute!(
-                stdout(),
-                MoveToColumn(0),
-                Clear(ClearType::UntilNewLine),
-                SetForegroundColor(Color::White),
-                Print("rsh: "),
-                SetForegroundColor(Color::Red),
-                Print(err.message),
-                Print("\n"),
-                SetForegroundColor(Color::White),
-            ) {
+            let mut stderr = std::io::stderr();
+            // 端末では入力途中の行を消してから表示する
+            let result = if interactive {
+                execute!(stderr, MoveToColumn(0), Clear(ClearType::UntilNewLine))
+            } else {
+                Ok(())
+            };
+            if let Err(e) =
+                result.and_then(|_| write_error(&mut stderr, &err.message, color_enabled))
+            {
                 eprintln!("Failed to execute command: {}", e);
             }
         }
+        Ok(Status::Exit(code)) => std::process::exit(code),
         _ => (),
     }
 }
+
+// カレントディレクトリや環境変数はプロセス全体で共有されるので、
+// 書き換えるテストは順番に実行し、終わったら元に戻す
+#[cfg(test)]
+static PROCESS_STATE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+#[cfg(test)]
+struct SavedProcessState {
+    _lock: std::sync::MutexGuard<'static, ()>,
+    dir: PathBuf,
+    vars: Vec<(&'static str, Option<OsString>)>,
+}
+
+#[cfg(test)]
+fn save_process_state() -> SavedProcessState {
+    let lock = PROCESS_STATE.lock().unwrap_or_else(|err| err.into_inner());
+    SavedProcessState {
+        _lock: lock,
+        dir: env::current_dir().unwrap(),
+        vars: ["PWD", "OLDPWD", "HOME", "HISTFILE", "USER", "LOGNAME"]
+            .into_iter()
+            .map(|name| (name, env::var_os(name)))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+impl Drop for SavedProcessState {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.dir);
+        for (name, value) in &self.vars {
+            match value {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::Command;
+
+    fn ansi(command: impl Command) -> String {
+        let mut text = String::new();
+        command.write_ansi(&mut text).unwrap();
+        text
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    fn quiet_rsh() -> Rsh {
+        // 端末を前提にした後始末や色付けをしない
+        let mut rsh = Rsh::new();
+        rsh.color_enabled = false;
+        rsh.interactive = false;
+        rsh
+    }
+
+    #[test]
+    fn display_width_skips_escape_sequences() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("\x1b[38;2;1;2;3mab\x1b[0m"), 2);
+        assert_eq!(display_width("\x1b]0;title\x07x"), 1);
+    }
+
+    #[test]
+    fn inner_word_range_selects_word_or_spaces() {
+        let line = "echo hello  world";
+        assert_eq!(inner_word_range(line, 0), Some((0, 3)));
+        assert_eq!(inner_word_range(line, 7), Some((5, 9)));
+        assert_eq!(inner_word_range(line, 10), Some((10, 11)));
+        assert_eq!(inner_word_range(line, 16), Some((12, 16)));
+        assert_eq!(inner_word_range(line, 17), None);
+    }
+
+    #[test]
+    fn rshenv_statements_skip_blanks_and_comments() {
+        let data = "a=1\r\n\n# comment\n  setenv X y  \r\n";
+        let statements: Vec<&str> = rshenv_statements(data).collect();
+        assert_eq!(statements, ["a=1", "setenv X y"]);
+    }
+
+    #[test]
+    fn exit_cursor_style_defaults_to_user_shape() {
+        assert_eq!(ansi(exit_cursor_style(Some("steady-bar"))), "\x1b[6 q");
+        assert_eq!(ansi(exit_cursor_style(Some("blinking-block"))), "\x1b[1 q");
+        assert_eq!(ansi(exit_cursor_style(Some("unknown"))), "\x1b[0 q");
+        assert_eq!(ansi(exit_cursor_style(None)), "\x1b[0 q");
+    }
+
+    #[test]
+    fn split_line_keeps_quotes_and_empty_words() {
+        assert_eq!(
+            rsh_split_line("echo 'a b' c".to_string()),
+            ["echo", "'a b'", "c"]
+        );
+        assert_eq!(rsh_split_line("a\\ b  c".to_string()), ["a\\ b", "", "c"]);
+        assert_eq!(rsh_split_line(String::new()), [""]);
+        assert_eq!(
+            rsh_split_line("echo \"a b\"  c".to_string()),
+            ["echo", "\"a b\"", "", "c"]
+        );
+    }
+
+    #[test]
+    fn split_line_round_trips_runs_of_spaces() {
+        for line in ["a  b   c", "  a ", "echo 'x  y'   z"] {
+            assert_eq!(rsh_split_line(line.to_string()).join(" "), line);
+        }
+    }
+
+    #[test]
+    fn strip_comment_only_at_word_start() {
+        assert_eq!(rsh_strip_comment("echo a # comment"), "echo a");
+        assert_eq!(rsh_strip_comment("echo a#b"), "echo a#b");
+        assert_eq!(rsh_strip_comment("echo '# not' a"), "echo '# not' a");
+        assert_eq!(rsh_strip_comment("echo a\\ #b"), "echo a\\ #b");
+    }
+
+    #[test]
+    fn scan_line_state_reports_open_constructs() {
+        assert_eq!(scan_line_state("echo 'a"), (Some('\''), false, 0));
+        assert_eq!(scan_line_state("echo a \\"), (None, true, 0));
+        assert_eq!(scan_line_state("f ( {"), (None, false, 2));
+        assert_eq!(scan_line_state("echo one # don't"), (None, false, 0));
+        assert_eq!(scan_line_state("# (\necho \"a"), (Some('"'), false, 0));
+        assert!(is_line_complete("echo 'a'"));
+        assert!(!is_line_complete("echo (a"));
+    }
+
+    #[test]
+    fn next_completion_word_stops_after_one_word() {
+        assert_eq!(next_completion_word("git", "git commit -m x"), " commit");
+        assert_eq!(next_completion_word("git co", "git commit -m x"), "mmit");
+        assert_eq!(next_completion_word("ls", "cat"), "");
+    }
+
+    #[test]
+    fn split_last_word_at_last_space() {
+        assert_eq!(split_last_word("cd src/ma"), ("cd ", "src/ma"));
+        assert_eq!(split_last_word("ls"), ("", "ls"));
+    }
+
+    #[test]
+    fn percent_encode_path_escapes_unsafe_bytes() {
+        assert_eq!(percent_encode_path(Path::new("/tmp/a b")), "/tmp/a%20b");
+        assert_eq!(percent_encode_path(Path::new("/日")), "/%E6%97%A5");
+    }
+
+    #[test]
+    fn format_elapsed_switches_to_minutes() {
+        assert_eq!(format_elapsed(Duration::from_millis(3400)), "3.4s");
+        assert_eq!(format_elapsed(Duration::from_secs(125)), "2m5s");
+        assert_eq!(format_duration(Duration::from_millis(61500)), "1m1.500s");
+    }
+
+    #[test]
+    fn error_written_with_prefix() {
+        let mut out = Vec::new();
+        write_error(&mut out, "cd: x: no such file or directory", false).unwrap();
+        assert_eq!(out, b"rsh: cd: x: no such file or directory\n");
+
+        let mut out = Vec::new();
+        write_error(&mut out, "oops", true).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[38;5;15mrsh: \x1b[38;5;9moops\x1b[38;5;15m\n"
+        );
+    }
+
+    #[test]
+    fn trace_written_without_prefix() {
+        let args = ["echo".to_string(), "a  b".to_string(), String::new()];
+        let mut out = Vec::new();
+        rsh_trace(&mut out, &[], &args).unwrap();
+        assert_eq!(out, b"+ echo 'a  b' ''\n");
+
+        let env_vars = [("FOO".to_string(), "x y".to_string())];
+        let mut out = Vec::new();
+        rsh_trace(&mut out, &env_vars, &args[..1]).unwrap();
+        assert_eq!(out, b"+ FOO='x y' echo\n");
+    }
+
+    #[test]
+    fn prompt_renders_into_sink() {
+        let _state = save_process_state();
+        colored::control::set_override(false);
+        let mut rsh = Rsh::new();
+        rsh.color_enabled = false;
+        rsh.interactive = false;
+
+        let mut out = Vec::new();
+        rsh.render_prompt(&mut out).unwrap();
+        let dirs: String = rsh
+            .get_current_dir_as_vec()
+            .iter()
+            .map(|dir| format!("{}/", dir))
+            .collect();
+        let expected = format!(
+            "\x1b[1G\x1b[K{} {} [0: N] > ",
+            username(whoami::fallible::username().ok()),
+            dirs
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+
+        rsh.continuation = "echo 'a\n".to_string();
+        let mut out = Vec::new();
+        rsh.render_prompt(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\x1b[1G\x1b[K> ");
+    }
+
+    #[test]
+    fn split_assignments_before_command() {
+        let (env_vars, args) = rsh_split_assignments(strings(&["FOO=bar", "cmd", "arg"]));
+        assert_eq!(env_vars, [("FOO".to_string(), "bar".to_string())]);
+        assert_eq!(args, ["cmd", "arg"]);
+
+        // 変数名として正しくなければコマンドとして扱う
+        let (env_vars, args) = rsh_split_assignments(strings(&["1X=y", "cmd"]));
+        assert!(env_vars.is_empty());
+        assert_eq!(args, ["1X=y", "cmd"]);
+
+        let (env_vars, args) = rsh_split_assignments(strings(&["A=1", "B="]));
+        assert_eq!(
+            env_vars,
+            [
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), String::new())
+            ]
+        );
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn assignment_prefix_is_temporary_for_builtins() {
+        let mut rsh = quiet_rsh();
+        env::remove_var("RSH_PREFIX_T");
+        env::set_var("RSH_PREFIX_OLD_T", "old");
+
+        // printenv は変数が無ければ失敗する
+        rsh.rsh_execute(strings(&["RSH_PREFIX_T=1", "printenv", "RSH_PREFIX_T"]))
+            .unwrap();
+        assert_eq!(rsh.return_code, 0);
+        assert!(env::var("RSH_PREFIX_T").is_err());
+
+        rsh.rsh_execute(strings(&[
+            "RSH_PREFIX_OLD_T=new",
+            "printenv",
+            "RSH_PREFIX_OLD_T",
+        ]))
+        .unwrap();
+        assert_eq!(rsh.return_code, 0);
+        assert_eq!(env::var("RSH_PREFIX_OLD_T").unwrap(), "old");
+    }
+
+    fn seeded_rsh(commands: &[&str]) -> Rsh {
+        let mut rsh = quiet_rsh();
+        rsh.history_database = commands
+            .iter()
+            .map(|command| History::new(command.to_string(), String::new()))
+            .collect();
+        rsh
+    }
+
+    #[test]
+    fn history_words_skip_empty_elements() {
+        assert_eq!(rsh_history_word("ls -l  /tmp", 0).unwrap(), "ls");
+        assert_eq!(rsh_history_word("ls -l  /tmp", 2).unwrap(), "/tmp");
+        assert_eq!(rsh_history_word("ls -l  /tmp", usize::MAX).unwrap(), "/tmp");
+        assert_eq!(rsh_history_word("ls", 1), None);
+    }
+
+    #[test]
+    fn expand_history_designators() {
+        let rsh = seeded_rsh(&["echo one two", "ls -l  /tmp"]);
+        let expand = |line: &str| rsh.rsh_expand_history(line).unwrap();
+        assert_eq!(expand("echo !$"), "echo /tmp");
+        assert_eq!(expand("echo !^"), "echo -l");
+        assert_eq!(expand("echo !!:2"), "echo /tmp");
+        assert_eq!(expand("!!"), "ls -l  /tmp");
+        // !n は1始まりの履歴番号
+        assert_eq!(expand("!1"), "echo one two");
+        // シングルクォートの中は展開しない
+        assert_eq!(expand("echo '!$' \"!$\""), "echo '!$' \"/tmp\"");
+    }
+
+    #[test]
+    fn expand_history_errors() {
+        let rsh = seeded_rsh(&["ls -l"]);
+        let error = |line: &str| rsh.rsh_expand_history(line).unwrap_err().message;
+        assert_eq!(error("echo !!:5"), "!!:5: bad word specifier");
+        assert_eq!(error("echo !!:x"), "!!:: bad word specifier");
+        assert_eq!(error("!3"), "!3: event not found");
+        assert_eq!(error("!0"), "!0: event not found");
+
+        let rsh = seeded_rsh(&[]);
+        assert_eq!(
+            rsh.rsh_expand_history("echo !$").unwrap_err().message,
+            "!$: event not found"
+        );
+    }
+
+    #[test]
+    fn unquote_word_removes_quotes_and_escapes() {
+        assert_eq!(unquote_word("\"\""), (String::new(), true));
+        assert_eq!(unquote_word("''"), (String::new(), true));
+        assert_eq!(unquote_word(""), (String::new(), false));
+        assert_eq!(unquote_word(r#""a\"b""#), ("a\"b".to_string(), true));
+        assert_eq!(unquote_word(r"a\ b"), ("a b".to_string(), false));
+        assert_eq!(unquote_word(r#"'a\"b'"#), ("a\\\"b".to_string(), true));
+    }
+
+    #[test]
+    fn unquote_args_keeps_empty_quoted_arguments() {
+        let rsh = quiet_rsh();
+        let line = r#"echo "" ''   "a\"b" a\ b"#;
+        let args = rsh
+            .rsh_unquote_args(rsh_split_line(line.to_string()))
+            .unwrap();
+        assert_eq!(args, ["echo", "", "", "a\"b", "a b"]);
+    }
+
+    #[test]
+    fn expand_tilde_pwd_and_oldpwd() {
+        let _state = save_process_state();
+        let mut rsh = quiet_rsh();
+        env::set_var("HOME", "/home/rsh");
+        env::set_var("PWD", "/work/here");
+        env::set_var("OLDPWD", "/work/before");
+        assert_eq!(
+            rsh.rsh_expand_tilde("~/src").unwrap(),
+            ("/home/rsh".to_string(), "/src")
+        );
+        assert_eq!(
+            rsh.rsh_expand_tilde("~+/a").unwrap(),
+            ("/work/here".to_string(), "/a")
+        );
+        assert_eq!(
+            rsh.rsh_expand_tilde("~-").unwrap(),
+            ("/work/before".to_string(), "")
+        );
+        // 展開しない単語はそのまま返す
+        assert_eq!(rsh.rsh_expand_tilde("a~").unwrap(), (String::new(), "a~"));
+        // シングルクォートの中は文字通り
+        let args = rsh
+            .rsh_unquote_args(rsh_split_line("echo '~-' ~-".to_string()))
+            .unwrap();
+        assert_eq!(args, ["echo", "~-", "/work/before"]);
+
+        env::remove_var("OLDPWD");
+        assert_eq!(
+            rsh.rsh_expand_tilde("~-").unwrap_err().message,
+            "~-: OLDPWD not set"
+        );
+        rsh.directory_stack = vec!["/stacked".to_string()];
+        assert_eq!(
+            rsh.rsh_expand_tilde("~1").unwrap(),
+            ("/stacked".to_string(), "")
+        );
+    }
+
+    #[test]
+    fn repeat_runs_the_command_count_times() {
+        let _state = save_process_state();
+        let base = fs::canonicalize(env::temp_dir())
+            .unwrap()
+            .join(format!("rsh-repeat-test-{}", std::process::id()));
+        let deep = base.join("a/b/c");
+        fs::create_dir_all(&deep).unwrap();
+        env::set_current_dir(&deep).unwrap();
+        env::set_var("PWD", &deep);
+
+        let mut rsh = quiet_rsh();
+        rsh.rsh_repeat(3, &strings(&["cd", ".."])).unwrap();
+        assert_eq!(env::current_dir().unwrap(), base);
+        assert_eq!(rsh.return_code, 0);
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn rerun_writes_the_command_to_history() {
+        let _state = save_process_state();
+        let histfile = env::temp_dir().join(format!("rsh-rerun-test-{}", std::process::id()));
+        let _ = fs::remove_file(&histfile);
+        env::set_var("HISTFILE", &histfile);
+
+        let mut rsh = seeded_rsh(&["RSH_RERUN_T=1", "r"]);
+        rsh.rsh_rerun().unwrap();
+        let records = csv_reader(histfile.to_str().unwrap()).unwrap();
+        fs::remove_file(&histfile).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get_command(), "RSH_RERUN_T=1");
+
+        let mut rsh = seeded_rsh(&["r"]);
+        rsh.rsh_rerun().unwrap();
+        assert_eq!(rsh.return_code, 1);
+    }
+
+    #[test]
+    fn prompt_dirs_home_short_and_deep() {
+        let home = Some(Path::new("/home/rsh"));
+        let dirs = |cwd: &str| prompt_dirs(Path::new(cwd), home);
+        assert_eq!(dirs("/home/rsh"), ["~"]);
+        assert_eq!(dirs("/home/rsh/src/rsh"), ["~", "src", "rsh"]);
+        // ルートは空の要素で表し、後ろに付く / で / になる
+        assert_eq!(dirs("/usr/bin"), ["", "usr", "bin"]);
+        assert_eq!(dirs("/home/rshx"), ["", "home", "rshx"]);
+        assert_eq!(dirs("/a/b/c/d/e/f"), ["", "…", "d", "e", "f"]);
+        assert_eq!(dirs("/home/rsh/a/b/c/d/e"), ["~", "…", "c", "d", "e"]);
+        assert_eq!(prompt_dirs(Path::new("/usr"), None), ["", "usr"]);
+    }
+
+    #[test]
+    fn rshenv_variable_is_written_once() {
+        let path = env::temp_dir().join(format!("rsh-rshenv-test-{}", std::process::id()));
+        fs::write(&path, "auto_save=true\n").unwrap();
+        let read = || fs::read_to_string(&path).unwrap();
+
+        write_rshenv_variable(&path, "A", "1").unwrap();
+        write_rshenv_variable(&path, "A", "1").unwrap();
+        assert_eq!(read(), "auto_save=true\nsetenv A 1\n");
+
+        // 値が変われば古い行を置き換え、空の値は末尾に空白を付けない
+        write_rshenv_variable(&path, "A", "").unwrap();
+        assert_eq!(read(), "auto_save=true\nsetenv A\n");
+        write_rshenv_variable(&path, "A", "2 3").unwrap();
+        write_rshenv_variable(&path, "AB", "4").unwrap();
+        assert_eq!(read(), "auto_save=true\nsetenv A 2 3\nsetenv AB 4\n");
+
+        let err = write_rshenv_variable(&path, "A", "x\nsetenv B y").unwrap_err();
+        assert_eq!(
+            err.message,
+            "rshenv: A: cannot save a value containing a line break"
+        );
+        assert_eq!(read(), "auto_save=true\nsetenv A 2 3\nsetenv AB 4\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn confirmation_matches_whole_command_words() {
+        let patterns = Some("rm, git push ,");
+        assert!(needs_confirmation(patterns, "rm -rf x"));
+        assert!(needs_confirmation(patterns, "  rm"));
+        assert!(needs_confirmation(patterns, "git push origin"));
+        assert!(!needs_confirmation(patterns, "rmdir x"));
+        assert!(!needs_confirmation(patterns, "git pull"));
+        // 設定が無い・空なら何も確認しない
+        assert!(!needs_confirmation(Some(""), "rm -rf x"));
+        assert!(!needs_confirmation(None, "rm -rf x"));
+    }
+
+    #[test]
+    fn errexit_stops_the_rest_of_the_list() {
+        let mut rsh = quiet_rsh();
+        rsh.options.errexit = true;
+        env::remove_var("RSH_ERREXIT_T");
+        let status = rsh
+            .rsh_run_line("cd /nonexistent\nsetenv RSH_ERREXIT_T 1")
+            .unwrap();
+        assert!(matches!(status, Status::Exit(1)));
+        assert!(env::var("RSH_ERREXIT_T").is_err());
+
+        // errexit が無ければ失敗しても続ける
+        rsh.options.errexit = false;
+        let status = rsh
+            .rsh_run_line("cd /nonexistent\nsetenv RSH_ERREXIT_T 1")
+            .unwrap();
+        assert!(matches!(status, Status::Success));
+        assert_eq!(env::var("RSH_ERREXIT_T").unwrap(), "1");
+    }
+
+    #[test]
+    fn directory_rc_is_applied_and_restored() {
+        let _state = save_process_state();
+        let base = fs::canonicalize(env::temp_dir())
+            .unwrap()
+            .join(format!("rsh-directory-rc-test-{}", std::process::id()));
+        let project = base.join("project");
+        fs::create_dir_all(project.join("sub")).unwrap();
+        fs::write(
+            project.join(".rshrc"),
+            "setenv RSH_DIRRC_T inside\nRSH_DIRRC_U=new\n",
+        )
+        .unwrap();
+        env::set_var("RSH_DIRRC_T", "outside");
+        env::remove_var("RSH_DIRRC_U");
+
+        let mut rsh = quiet_rsh();
+        rsh.config_database
+            .insert("directory_rc".to_string(), "true".to_string());
+        rsh.config_database.insert(
+            "directory_rc_allow".to_string(),
+            project.to_string_lossy().into_owned(),
+        );
+
+        env::set_current_dir(&project).unwrap();
+        rsh.update_directory_rc();
+        assert_eq!(env::var("RSH_DIRRC_T").unwrap(), "inside");
+        assert_eq!(env::var("RSH_DIRRC_U").unwrap(), "new");
+
+        // 配下のディレクトリにいる間はそのまま
+        env::set_current_dir(project.join("sub")).unwrap();
+        rsh.update_directory_rc();
+        assert_eq!(env::var("RSH_DIRRC_T").unwrap(), "inside");
+
+        // 離れたら元の値に戻し、無かった変数は消す
+        env::set_current_dir(&base).unwrap();
+        rsh.update_directory_rc();
+        assert_eq!(env::var("RSH_DIRRC_T").unwrap(), "outside");
+        assert!(env::var("RSH_DIRRC_U").is_err());
+        env::remove_var("RSH_DIRRC_T");
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn delete_forward_removes_one_character() {
+        let mut buffer = "abc".to_string();
+        delete_forward(&mut buffer, 1);
+        assert_eq!(buffer, "ac");
+
+        let mut buffer = "aあいb".to_string();
+        delete_forward(&mut buffer, 1);
+        assert_eq!(buffer, "aいb");
+        delete_forward(&mut buffer, 1);
+        assert_eq!(buffer, "ab");
+
+        // 行末では何もしない
+        delete_forward(&mut buffer, 2);
+        assert_eq!(buffer, "ab");
+        let mut buffer = String::new();
+        delete_forward(&mut buffer, 0);
+        assert_eq!(buffer, "");
+    }
+
+    #[test]
+    fn home_and_end_columns_over_wide_text() {
+        let mut rsh = quiet_rsh();
+        rsh.buffer.buffer = "aあb日".to_string();
+        let start = rsh.prompt.len();
+
+        // End: 末尾の文字の後ろ (全角文字は2列分)
+        rsh.set_cursor_char(rsh.buffer.buffer.chars().count());
+        assert_eq!((rsh.cursor_x, rsh.char_count), (8, 4));
+        assert_eq!(rsh.column_of(rsh.char_count), start + 6);
+
+        // Home: 行頭
+        rsh.set_cursor_char(0);
+        assert_eq!((rsh.cursor_x, rsh.char_count), (0, 0));
+        assert_eq!(rsh.column_of(rsh.char_count), start);
+
+        // 範囲外の位置は行末に丸める
+        rsh.set_cursor_char(10);
+        assert_eq!((rsh.cursor_x, rsh.char_count), (8, 4));
+    }
+
+    #[test]
+    fn history_matches_are_newest_first_and_unique() {
+        let rsh = seeded_rsh(&["ls -l", "cd /tmp", "ls -a", "ls -l", "echo ls"]);
+        assert_eq!(rsh.get_history_matches("ls"), strings(&["ls -l", "ls -a"]));
+        assert_eq!(rsh.get_history_matches("cd"), strings(&["cd /tmp"]));
+        assert!(rsh.get_history_matches("git").is_empty());
+        assert_eq!(rsh.get_history_matches("").len(), 4);
+    }
+
+    #[test]
+    fn filtered_commands_list_each_command_once() {
+        let mut rsh = seeded_rsh(&["git status", "grep"]);
+        rsh.directory_database = strings(&["gradle/", "src/"]);
+        rsh.command_database = strings(&["grep", "git", "gzip", "ls"]);
+        assert_eq!(
+            rsh.get_filtered_commands("g"),
+            strings(&["grep", "git status", "gradle/", "git", "gzip"])
+        );
+    }
+
+    #[test]
+    fn directory_completions_skip_files_and_hidden_dirs() {
+        let base = env::temp_dir().join(format!("rsh-dir-completion-{}", std::process::id()));
+        for dir in ["src", "scripts", ".git", ".config"] {
+            fs::create_dir_all(base.join(dir)).unwrap();
+        }
+        fs::write(base.join("setup.sh"), "").unwrap();
+        let base = base.to_str().unwrap();
+
+        let rsh = quiet_rsh();
+        assert_eq!(
+            rsh.get_directory_completions(&format!("cd {}/s", base)),
+            vec![format!("cd {}/scripts/", base), format!("cd {}/src/", base)]
+        );
+        // 隠しディレクトリは . を入力したときだけ
+        assert_eq!(
+            rsh.get_directory_completions(&format!("cd {}/", base))
+                .len(),
+            2
+        );
+        assert_eq!(
+            rsh.get_directory_completions(&format!("cd {}/.g", base)),
+            vec![format!("cd {}/.git/", base)]
+        );
+        assert!(rsh
+            .get_directory_completions(&format!("cd {}/setup", base))
+            .is_empty());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn registered_completions_from_rshenv() {
+        let _state = save_process_state();
+        let home = env::temp_dir().join(format!("rsh-complete-test-{}", std::process::id()));
+        fs::create_dir_all(&home).unwrap();
+        fs::write(
+            home.join(".rshenv"),
+            "complete git= add, commit ,push,\nediting_mode=emacs\nsetenv A 1\n/usr/bin\n",
+        )
+        .unwrap();
+        env::set_var("HOME", &home);
+
+        let mut rsh = quiet_rsh();
+        rsh.get_rshenv_contents().unwrap();
+        assert_eq!(
+            rsh.completion_database.get("git"),
+            Some(&strings(&["add", "commit", "push"]))
+        );
+        assert_eq!(rsh.env_database, strings(&["/usr/bin"]));
+        assert!(!rsh.config_database.contains_key("complete git"));
+
+        assert_eq!(
+            rsh.get_completion_candidates("git co"),
+            strings(&["git commit"])
+        );
+        assert_eq!(
+            rsh.get_completion_candidates("git "),
+            strings(&["git add", "git commit", "git push"])
+        );
+        fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn cursor_moves_stop_at_the_buffer_edges() {
+        let mut rsh = quiet_rsh();
+        rsh.buffer.buffer = "aあ".to_string();
+        let mut range = String::new();
+
+        // 行頭で左に動かしても何も変わらない
+        rsh.set_cursor_char(0);
+        rsh.move_cursor_left(&mut stdout(), "left", &mut range);
+        assert_eq!((rsh.cursor_x, rsh.char_count), (0, 0));
+        assert_eq!(range, "");
+
+        // 行末で右に動かしても何も変わらない
+        rsh.set_cursor_char(2);
+        rsh.move_cursor_right(&mut stdout(), "right", &mut range);
+        assert_eq!((rsh.cursor_x, rsh.char_count), (4, 2));
+        assert_eq!(range, "");
+        assert_eq!(rsh.buffer.buffer, "aあ");
+    }
+
+    #[test]
+    fn exit_code_of_exit_and_signal() {
+        let pid = nix::unistd::Pid::from_raw(1);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 0)), 0);
+        assert_eq!(exit_code(WaitStatus::Exited(pid, 3)), 3);
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGINT, false)),
+            130
+        );
+        assert_eq!(
+            exit_code(WaitStatus::Signaled(pid, Signal::SIGKILL, true)),
+            137
+        );
+    }
+
+    #[test]
+    fn visual_delete_over_wide_characters() {
+        let mut rsh = quiet_rsh();
+        rsh.buffer.buffer = "aあいうb".to_string();
+        rsh.set_cursor_char(3);
+        rsh.delete_selection(1);
+        assert_eq!(rsh.buffer.buffer, "ab");
+        assert_eq!((rsh.cursor_x, rsh.char_count), (1, 1));
+
+        // 左向きに選択した場合も同じ範囲を消す
+        rsh.buffer.buffer = "aあいうb".to_string();
+        rsh.set_cursor_char(1);
+        rsh.delete_selection(3);
+        assert_eq!(rsh.buffer.buffer, "ab");
+        assert_eq!((rsh.cursor_x, rsh.char_count), (1, 1));
+
+        // 行末まで消したらカーソルは残った最後の文字に置く
+        rsh.buffer.buffer = "aあい".to_string();
+        rsh.set_cursor_char(2);
+        rsh.delete_selection(1);
+        assert_eq!(rsh.buffer.buffer, "a");
+        assert_eq!((rsh.cursor_x, rsh.char_count), (0, 0));
+    }
+
+    #[test]
+    fn column_of_counts_full_width_as_two() {
+        let mut rsh = quiet_rsh();
+        rsh.buffer.buffer = "aあb日c".to_string();
+        let start = rsh.prompt.len();
+        let columns: Vec<usize> = (0..=5).map(|index| rsh.column_of(index) - start).collect();
+        assert_eq!(columns, [0, 1, 3, 4, 6, 7]);
+        // 行末より後ろは行末の列
+        assert_eq!(rsh.column_of(9) - start, 7);
+    }
+
+    #[test]
+    fn emacs_mode_never_leaves_input() {
+        let mut rsh = quiet_rsh();
+        rsh.editing_mode = EditingMode::Emacs;
+        for mode in [Mode::Nomal, Mode::Visual, Mode::Input] {
+            rsh.set_mode(mode);
+            assert!(rsh.now_mode == Mode::Input);
+        }
+        rsh.editing_mode = EditingMode::Modal;
+        rsh.set_mode(Mode::Nomal);
+        assert!(rsh.now_mode == Mode::Nomal);
+    }
+
+    #[test]
+    fn emacs_ctrl_p_and_ctrl_n_walk_history() {
+        let ctrl = KeyModifiers::CONTROL;
+        let emacs = EditingMode::Emacs;
+        assert_eq!(history_key(emacs, KeyCode::Char('p'), ctrl), KeyCode::Up);
+        assert_eq!(history_key(emacs, KeyCode::Char('n'), ctrl), KeyCode::Down);
+        assert_eq!(
+            history_key(emacs, KeyCode::Char('p'), KeyModifiers::NONE),
+            KeyCode::Char('p')
+        );
+        // vim風の方式では補完候補の切り替えのまま
+        assert_eq!(
+            history_key(EditingMode::Modal, KeyCode::Char('n'), ctrl),
+            KeyCode::Char('n')
+        );
+    }
+
+    #[test]
+    fn username_falls_back_to_the_environment() {
+        let _state = save_process_state();
+        env::set_var("USER", "from-user");
+        env::set_var("LOGNAME", "from-logname");
+        assert_eq!(username(Some("alice".to_string())), "alice");
+        assert_eq!(username(Some(String::new())), "from-user");
+        assert_eq!(username(None), "from-user");
+
+        env::set_var("USER", "");
+        assert_eq!(username(None), "from-logname");
+        env::remove_var("LOGNAME");
+        assert_eq!(username(None), "user");
+    }
+
+    #[test]
+    fn histfile_redirects_the_history() {
+        let _state = save_process_state();
+        let home = env::temp_dir().join(format!("rsh-histfile-test-{}", std::process::id()));
+        fs::create_dir_all(&home).unwrap();
+        env::set_var("HOME", &home);
+        let home = home.to_str().unwrap();
+
+        let mut rsh = quiet_rsh();
+        env::remove_var("HISTFILE");
+        assert_eq!(
+            rsh.history_path().unwrap(),
+            format!("{}/.rsh_history", home)
+        );
+
+        // .rshenv の設定より環境変数を優先し、~ は HOME に展開する
+        rsh.config_database
+            .insert("HISTFILE".to_string(), "~/from-rshenv".to_string());
+        assert_eq!(rsh.history_path().unwrap(), format!("{}/from-rshenv", home));
+        env::set_var("HISTFILE", "~/logs/history");
+        assert_eq!(
+            rsh.history_path().unwrap(),
+            format!("{}/logs/history", home)
+        );
+
+        // 書き込みは親ディレクトリを作って HISTFILE にだけ行う
+        rsh.rsh_write_history("echo redirected").unwrap();
+        let records = csv_reader(&format!("{}/logs/history", home)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].get_command(), "echo redirected");
+        assert!(!Path::new(&format!("{}/.rsh_history", home)).exists());
+        fs::remove_dir_all(home).unwrap();
+    }
+
+    #[test]
+    fn profile_path_follows_home_not_cwd() {
+        let _state = save_process_state();
+        let base = fs::canonicalize(env::temp_dir()).unwrap();
+        env::set_var("HOME", "/home/rsh");
+        let rsh = quiet_rsh();
+        assert_eq!(rsh.open_profile(".rshenv").unwrap(), "/home/rsh/.rshenv");
+        env::set_current_dir(&base).unwrap();
+        assert_eq!(rsh.open_profile(".rshenv").unwrap(), "/home/rsh/.rshenv");
+
+        // HOME が無ければ passwd のホームディレクトリを使う
+        env::remove_var("HOME");
+        if let Some(home) = passwd_home() {
+            assert_eq!(
+                rsh.open_profile(".rshenv").unwrap(),
+                format!("{}/.rshenv", home.display())
+            );
+        }
+    }
+
+    #[test]
+    fn shadowed_builtins_skip_path_first_commands() {
+        let lookup = |name: &str| {
+            ["cd", "cat", "printenv", "ls"]
+                .contains(&name)
+                .then(|| PathBuf::from(format!("/usr/bin/{}", name)))
+        };
+        // cat は PATH の方が優先されるので知らせない
+        assert_eq!(
+            shadowed_builtins(lookup),
+            [
+                ("cd", PathBuf::from("/usr/bin/cd")),
+                ("printenv", PathBuf::from("/usr/bin/printenv"))
+            ]
+        );
+        assert!(shadowed_builtins(|_| None).is_empty());
+    }
+
+    #[test]
+    fn dbg_reports_database_sizes() {
+        let mut rsh = seeded_rsh(&["ls", "cd /tmp", "ls"]);
+        rsh.command_database = strings(&["ls", "cat"]);
+        rsh.env_database = strings(&["/usr/bin"]);
+        let mut out = Vec::new();
+        rsh.rsh_dbg(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "rsh: command_database: 2\n\
+             rsh: history_database: 3\n\
+             rsh: env_database: 1\n\
+             rsh:   /usr/bin\n"
+        );
+    }
+
+    #[test]
+    fn line_scanners_agree_on_quotes_and_escapes() {
+        let line = r#"echo "a # b" \"!1 'c !1' \# d # e"#;
+        assert_eq!(rsh_strip_comment(line), r#"echo "a # b" \"!1 'c !1' \# d"#);
+        assert_eq!(
+            rsh_split_line(line.to_string()),
+            strings(&[
+                "echo",
+                "\"a # b\"",
+                "\\\"!1",
+                "'c !1'",
+                "\\#",
+                "d",
+                "#",
+                "e"
+            ])
+        );
+        // エスケープされた " はクォートを開かないので、行は完結している
+        assert!(is_line_complete(line));
+        let rsh = seeded_rsh(&["ls"]);
+        assert_eq!(
+            rsh.rsh_expand_history(line).unwrap(),
+            r#"echo "a # b" \"ls 'c !1' \# d # e"#
+        );
+
+        // シングルクォート内のバックスラッシュはエスケープしない
+        let line = r"echo 'a\' # b";
+        assert_eq!(rsh_strip_comment(line), r"echo 'a\'");
+        assert_eq!(
+            rsh_split_line(line.to_string()),
+            strings(&["echo", r"'a\'", "#", "b"])
+        );
+        assert!(is_line_complete(line));
+    }
+}